@@ -13,10 +13,13 @@
 
 // To conserve gas, efficient serialization is achieved through Borsh (http://borsh.io/)
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, json};
 use near_sdk::wee_alloc;
 use near_sdk::{env, near_bindgen};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use std::convert::TryInto;
 
@@ -28,142 +31,6798 @@ const LOWER_CASE_LETTERS: &str = "abcdefghijklmnopqrstuvwxyz";
 const UPPER_CASE_LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const NUMBERS: &str = "0123456789";
 const SPECIAL_CHARS: &str = "~!@#$%^&*()_-+=[]{}/\\|?,.<>'\"";
+// Special characters found on the first symbol page of common mobile keyboards
+const MOBILE_SPECIAL_CHARS: &str = "-/()$&@.,?!'\"";
+
+// Bit flags selecting character classes, e.g. CHARSET_LOWER | CHARSET_DIGITS
+const CHARSET_LOWER: u8 = 1;
+const CHARSET_UPPER: u8 = 2;
+const CHARSET_DIGITS: u8 = 4;
+const CHARSET_SPECIAL: u8 = 8;
+
+// sha256 rounds stretching (master, resource) before derive_password maps the result to characters
+const DERIVATION_ROUNDS: u32 = 1000;
+
+const MIN_PIN_DIGITS: u32 = 4;
+const MAX_PIN_DIGITS: u32 = 12;
+
+const DEFAULT_PASSWORD_LENGTH: u32 = 12;
+const MIN_PASSWORD_LENGTH: u32 = 4;
+const MAX_PASSWORD_LENGTH: u32 = 128;
+
+// Age boundaries (in nanoseconds, like env::block_timestamp) used by age_histogram
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const AGE_FRESH_NS: u64 = 30 * NANOS_PER_DAY;
+const AGE_QUARTER_NS: u64 = 90 * NANOS_PER_DAY;
+const AGE_YEAR_NS: u64 = 365 * NANOS_PER_DAY;
+
+// Limits on the free-form custom fields attached to a single key
+const MAX_CUSTOM_FIELDS: usize = 16;
+const MAX_CUSTOM_FIELDS_BYTES: usize = 2048;
+
+// Client-side ciphers a stored value can be tagged with; "none" marks a value kept as is
+const DEFAULT_ALGORITHM: &str = "none";
+const KNOWN_ALGORITHMS: [&str; 4] = [DEFAULT_ALGORITHM, "aes-256-gcm", "chacha20poly1305", "xchacha20poly1305"];
+
+// Second factors a key's account can be protected with, as recorded by set_two_factor
+const TWO_FACTOR_METHODS: [&str; 5] = ["sms", "email", "app", "hardware_key", "push"];
+
+// Default for the owner-configurable max_credentials_per_account
+const DEFAULT_MAX_CREDENTIALS_PER_ACCOUNT: u64 = 1000;
+
+// Byte limits on the names a key is stored under
+const MAX_RESOURCE_BYTES: usize = 128;
+const MAX_IDENTIFIER_BYTES: usize = 256;
+
+// Limits on off-chain attachment content identifiers (IPFS/Arweave CIDs) per key
+const MAX_ATTACHMENTS: usize = 8;
+const MAX_CID_BYTES: usize = 128;
+
+// Limits on the labels a key can be tagged with, and the bucket tag_counts uses for keys without any
+const MAX_TAGS: usize = 16;
+const MAX_TAG_BYTES: usize = 32;
+const UNTAGGED: &str = "untagged";
+
+// Security question/answer pairs a key can hold, and the byte limit on each question and answer
+const MAX_SECURITY_QUESTIONS: usize = 5;
+const MAX_SECURITY_QA_BYTES: usize = 256;
+
+// One-time backup codes a key can hold, and the byte limit on each code
+const MAX_BACKUP_CODES: usize = 32;
+const MAX_BACKUP_CODE_BYTES: usize = 64;
+
+// Bounds of the per-key clipboard clear timeout, in seconds
+const MIN_CLIPBOARD_CLEAR_SECS: u32 = 5;
+const MAX_CLIPBOARD_CLEAR_SECS: u32 = 600;
+
+// Byte limits on the address of the site a key belongs to and on its free-form notes
+const MAX_URL_BYTES: usize = 2048;
+const MAX_NOTES_BYTES: usize = 4096;
+
+// Byte limit on the reminder shown when a key is pulled up
+const MAX_REMINDER_BYTES: usize = 256;
+
+// Column order of a KeePass CSV export, as read by import_keepass_csv
+const KEEPASS_COLUMNS: [&str; 5] = ["Title", "Username", "Password", "URL", "Notes"];
+
+// Keyboard rows (QWERTY layout) and the run length at which a stretch of one, or of consecutive
+// characters like "abcd" and "4321", makes a password a weak pattern
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+const WEAK_PATTERN_RUN: usize = 4;
+
+// Vaults split one account's keys into namespaces. Keys of the default vault are stored under the
+// account id itself, those of a named vault under "<account id>:<vault>", which can never clash
+// with a real account id since those cannot contain ':'. Only storing, reading, deleting, listing
+// and moving keys take a vault; every other method works on the default vault.
+const DEFAULT_VAULT: &str = "default";
+const VAULT_SEPARATOR: char = ':';
+const MAX_VAULT_BYTES: usize = 64;
+
+// Keys of a team are stored under "#<team id>"; '#' cannot appear in account ids either
+const TEAM_PREFIX: char = '#';
+const MAX_TEAM_ID_BYTES: usize = 64;
+
+// Number of previous values kept per key
+const MAX_HISTORY: usize = 10;
+
+// Candidates drawn by constraint-based generators before they fall back to repairing the last one
+const MAX_GENERATION_ATTEMPTS: u32 = 16;
+
+// Candidates generate_with_requirements tries, more since requirements can reject most of them
+const MAX_REQUIREMENT_ATTEMPTS: u32 = 100;
+
+// Minimum length of the caller-supplied entropy generate_ephemeral mixes into its seed
+const MIN_EPHEMERAL_ENTROPY_BYTES: usize = 16;
+
+// Maximum number of grantees accepted by a single share_with_many call
+const MAX_SHARE_BATCH: usize = 32;
+
+// Maximum number of keys generated by a single generate_batch call
+const MAX_GENERATION_BATCH: usize = 32;
+
+// Capabilities reported by version_info, so frontends can degrade gracefully on older deployments.
+// Every optional capability a frontend may have UI for is listed: per-key data beyond the value and
+// identifier, and account-level workflows. Generation variants and owner-only settings aren't.
+const FEATURES: [&str; 28] = [
+    "sharing",
+    "vaults",
+    "teams",
+    "trash",
+    "history",
+    "tags",
+    "attachments",
+    "notes",
+    "backup_codes",
+    "site_rules",
+    "archiving",
+    "emergency_access",
+    "borsh_backup",
+    "keepass_import",
+    "custom_fields",
+    "urls",
+    "security_questions",
+    "auto_rotation",
+    "clipboard_clear",
+    "key_fingerprints",
+    "strength_guard",
+    "account_merge",
+    "two_phase_delete",
+    "access_tracking",
+    "reminders",
+    "reveal_tokens",
+    "two_factor",
+    "auto_lock",
+];
+
+// Estimated entropy at which password_strength reaches its maximum score of 100
+const FULL_STRENGTH_BITS: f64 = 100.0;
+
+// Characters two values must share before their trailing digits to count as near duplicates
+const NEAR_DUPLICATE_MIN_PREFIX: usize = 6;
+
+// Guesses per second assumed by crack_time_estimate: an offline attack on a fast hash with a
+// rack of GPUs
+const GUESSES_PER_SECOND: u128 = 10_000_000_000;
+
+// How long a request_delete stays confirmable
+const DELETE_CONFIRMATION_WINDOW_NS: u64 = 10 * 60 * 1_000_000_000;
 
 
 // Structs in Rust are similar to other languages, and may include impl keyword as shown below
 // Note: the names of the structs are not important when calling the smart contract, but the function names are
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
 pub struct Key {
     identifier: String,
-    enc_password: String
+    enc_password: String,
+    updated_at: u64,
+    // Kept as a Vec of pairs rather than a map so the Borsh layout stays in insertion order
+    custom_fields: Vec<(String, String)>,
+    shares: Vec<Share>,
+    // Previous values of enc_password with the time each was replaced, oldest first
+    history: Vec<(u64, String)>,
+    // Cipher the client used to encrypt enc_password, one of KNOWN_ALGORITHMS
+    algorithm: String,
+    // Content identifiers of encrypted files stored off-chain
+    attachments: Vec<String>,
+    // Normalized (trimmed, lowercase) labels, without duplicates
+    tags: Vec<String>,
+    // When set, rotate_due regenerates the value once it is older than this
+    rotate_every_ns: Option<u64>,
+    // Address of the site the key belongs to, empty when unknown
+    url: String,
+    // (question, answer) pairs; answers are as sensitive as enc_password
+    security_qa: Vec<(String, String)>,
+    // One-time (code, used) backup codes issued by the service, as sensitive as enc_password
+    backup_codes: Vec<(String, bool)>,
+    // Free-form text; may hold secrets, so it is as sensitive as enc_password
+    notes: String,
+    // GenerationOptions (as JSON) the current value was generated with by generate_with_options
+    generation_params: Option<String>,
+    // Hidden from list_resources without being deleted
+    archived: bool,
+    // Times the value was replaced by a different one; unlike history this is never truncated
+    rotation_count: u32,
+    // Seconds after which frontends should clear a copied value, None for their own default
+    clipboard_clear_secs: Option<u32>,
+    // Position in the order the account created its keys, see list_by_creation
+    creation_seq: u64,
+    // Block timestamp of the latest read through access_password, None if never read that way
+    last_accessed: Option<u64>,
+    // Short hint shown alongside the key, like "use VPN first"; not secret, unlike notes
+    reminder: Option<String>,
+    // Second factor configured on the service, one of TWO_FACTOR_METHODS
+    two_factor: Option<String>,
+}
+
+// Everything about a key except its value
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KeyMetadata {
+    pub identifier: String,
+    pub updated_at: u64,
+    pub algorithm: String,
+    pub reminder: Option<String>,
+}
+
+// Read access to a key granted by its owner to another account, optionally until `expires_at`
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct Share {
+    grantee: String,
+    expires_at: Option<u64>,
+}
+
+impl Share {
+    fn is_active(&self) -> bool {
+        self.expires_at.is_none_or(|expires_at| env::block_timestamp() < expires_at)
+    }
+}
+
+// Accounts sharing a team's keys; every member can read and write them, the owner manages members
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Team {
+    owner: String,
+    members: Vec<String>,
+}
+
+// Minimum number of characters a generated password must take from each character class
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClassMinimums {
+    pub lower: u32,
+    pub upper: u32,
+    pub digits: u32,
+    pub special: u32,
+}
+
+impl ClassMinimums {
+    fn by_class(&self) -> [(&'static str, u32); 4] {
+        [
+            (LOWER_CASE_LETTERS, self.lower),
+            (UPPER_CASE_LETTERS, self.upper),
+            (NUMBERS, self.digits),
+            (SPECIAL_CHARS, self.special),
+        ]
+    }
+}
+
+// Contract-wide rules a password is expected to follow, set by the owner
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PasswordPolicy {
+    pub min_length: u32,
+    pub require_lower: bool,
+    pub require_upper: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+}
+
+// Where generators take their randomness from. DeterministicCounter derives every seed from a
+// stored counter, so anyone can reproduce the generated passwords: it is only meant for tests and
+// local development, never for production.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum EntropyMode {
+    BlockchainRandom,
+    DeterministicCounter,
+}
+
+// Password rules of one site, kept in the owner-managed registry used by validate_against_domain
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SiteRules {
+    #[serde(default)]
+    pub max_length: Option<u32>,
+    #[serde(default)]
+    pub forbidden_chars: String,
+}
+
+impl SiteRules {
+    // Reasons only name the rule: echoing the offending characters would reveal part of the value
+    fn violations(&self, password: &str) -> Vec<String> {
+        let mut reasons = vec![];
+        if let Some(max_length) = self.max_length {
+            if password.chars().count() > max_length as usize {
+                reasons.push(format!("too long, at most {} characters allowed", max_length));
+            }
+        }
+        if password.chars().any(|c| self.forbidden_chars.contains(c)) {
+            reasons.push("contains forbidden characters".to_string());
+        }
+        reasons
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_lower: false,
+            require_upper: false,
+            require_digit: false,
+            require_special: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // Human-readable reasons the password breaks this policy, empty if it complies
+    fn violations(&self, password: &str) -> Vec<String> {
+        let mut reasons = vec![];
+        if (password.chars().count() as u64) < self.min_length as u64 {
+            reasons.push(format!("too short, at least {} characters required", self.min_length));
+        }
+        let has = |set: &str| password.chars().any(|c| set.contains(c));
+        if self.require_lower && !has(LOWER_CASE_LETTERS) {
+            reasons.push("missing lowercase letter".to_string());
+        }
+        if self.require_upper && !has(UPPER_CASE_LETTERS) {
+            reasons.push("missing uppercase letter".to_string());
+        }
+        if self.require_digit && !has(NUMBERS) {
+            reasons.push("missing digit".to_string());
+        }
+        if self.require_special && password.chars().all(|c| c.is_ascii_alphanumeric()) {
+            reasons.push("missing special character".to_string());
+        }
+        reasons
+    }
+}
+
+// Settings for generate_with_options; only the length is required in JSON
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GenerationOptions {
+    pub length: u32,
+    #[serde(default)]
+    pub minimums: ClassMinimums,
+    // Draw special characters from MOBILE_SPECIAL_CHARS only
+    #[serde(default)]
+    pub mobile_friendly: bool,
+    // Characters a target system forbids, removed from every class before generating
+    #[serde(default)]
+    pub exclude: String,
+    // Replaces the default special characters, for systems that accept a specific set only
+    #[serde(default)]
+    pub special_chars: Option<String>,
+}
+
+impl GenerationOptions {
+    // Character classes to draw from, each with the minimum it must contribute. Classes emptied by
+    // the exclusion are dropped, unless they have a minimum to meet.
+    fn character_classes(&self) -> Vec<(String, u32)> {
+        let mut classes: Vec<(String, u32)> = self.minimums.by_class().iter()
+            .map(|(class, min)| (class.to_string(), *min))
+            .collect();
+        if self.mobile_friendly {
+            classes[3].0 = MOBILE_SPECIAL_CHARS.to_string();
+        }
+        if let Some(special_chars) = &self.special_chars {
+            assert!(!self.mobile_friendly, "Custom special characters cannot be combined with mobile_friendly");
+            assert!(
+                !special_chars.chars().any(char::is_whitespace),
+                "Custom special characters must not contain whitespace"
+            );
+            classes[3].0 = special_chars.clone();
+        }
+
+        let mut remaining = vec![];
+        for (class, min) in classes.iter() {
+            let class: String = class.chars().filter(|c| !self.exclude.contains(*c)).collect();
+            if class.is_empty() {
+                assert!(*min == 0, "Excluding '{}' leaves nothing to meet a class minimum", self.exclude);
+            } else {
+                remaining.push((class, *min));
+            }
+        }
+        assert!(!remaining.is_empty(), "Excluding '{}' leaves no characters to generate from", self.exclude);
+        remaining
+    }
+}
+
+impl Key {
+    fn new(identifier: String, enc_password: String) -> Self {
+        Self {
+            identifier,
+            enc_password,
+            updated_at: env::block_timestamp(),
+            custom_fields: Vec::new(),
+            shares: Vec::new(),
+            history: Vec::new(),
+            algorithm: DEFAULT_ALGORITHM.to_string(),
+            attachments: Vec::new(),
+            tags: Vec::new(),
+            rotate_every_ns: None,
+            url: String::new(),
+            security_qa: Vec::new(),
+            backup_codes: Vec::new(),
+            notes: String::new(),
+            generation_params: None,
+            archived: false,
+            rotation_count: 0,
+            clipboard_clear_secs: None,
+            creation_seq: 0,
+            last_accessed: None,
+            reminder: None,
+            two_factor: None,
+        }
+    }
 }
 
 #[near_bindgen]
-#[derive(Default, BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize)]
 pub struct Keychain {
     keys: HashMap<String, HashMap<String, Key>>,
+    // Account allowed to change contract-wide settings, initially the contract account itself
+    owner_id: String,
+    // When set, policy violations are rejected instead of only being reported through events
+    strict_policy: bool,
+    // Source account -> account it agreed to have its keys merged into
+    merge_approvals: HashMap<String, String>,
+    policy: PasswordPolicy,
+    // Reverse index of shares: grantee -> (owner, resource) pairs shared with it
+    shared_with: HashMap<String, Vec<(String, String)>>,
+    max_credentials_per_account: u64,
+    // sha256 of each account's serialized keys, refreshed on every write
+    checksums: HashMap<String, Vec<u8>>,
+    // How long deleted keys stay restorable (0 deletes immediately), set by the owner
+    deletion_grace_period_ns: u64,
+    // Soft-deleted keys: account -> resource -> (key, deleted_at)
+    trash: HashMap<String, HashMap<String, (Key, u64)>>,
+    // Accounts permitted to write keys while allowlist_enforced is set, managed by the owner
+    allowed_accounts: Vec<String>,
+    allowlist_enforced: bool,
+    // Account the owner designated for break-glass reads of any key, if any
+    emergency_account: Option<String>,
+    // Account -> emergency account it agreed may break glass on its keys
+    emergency_consents: HashMap<String, String>,
+    teams: HashMap<String, Team>,
+    // Domain -> password rules of that site, set by the owner
+    site_rules: HashMap<String, SiteRules>,
+    // Two-phase deletions: account -> resource -> deadline of the confirm_delete call
+    pending_deletions: HashMap<String, HashMap<String, u64>>,
+    // When set, set_password reports weak values through a weak_password_stored event
+    warn_weak_passwords: bool,
+    // Accounts that opted into refusing weaker replacements in set_password
+    strength_guarded: Vec<String>,
+    // Account -> creation_seq the next key it creates gets
+    next_creation_seq: HashMap<String, u64>,
+    // Account -> (salt, salted sha256) of the client encryption key's fingerprint, both hex,
+    // checked by get_password_verified
+    key_fingerprints: HashMap<String, (String, String)>,
+    // Account -> identifier used for its keys stored with an empty one
+    default_identifiers: HashMap<String, String>,
+    entropy_mode: EntropyMode,
+    // Number of seeds drawn in DeterministicCounter mode
+    entropy_counter: u64,
+    // Block timestamp of the latest state-changing call, 0 before the first one
+    last_write_timestamp: u64,
+    // Single-use reveal tokens: owner -> hex sha256 of the token -> (resource, expires_at)
+    reveal_tokens: HashMap<String, HashMap<String, (String, u64)>>,
+    // Account -> how long it may stay inactive before needing a new unlock call
+    auto_lock_ns: HashMap<String, u64>,
+    // Account -> block timestamp of its latest unlock call or activity while unlocked
+    active_at: HashMap<String, u64>,
+}
+
+impl Default for Keychain {
+    fn default() -> Self {
+        Self {
+            keys: HashMap::new(),
+            owner_id: env::current_account_id(),
+            strict_policy: false,
+            merge_approvals: HashMap::new(),
+            policy: PasswordPolicy::default(),
+            shared_with: HashMap::new(),
+            max_credentials_per_account: DEFAULT_MAX_CREDENTIALS_PER_ACCOUNT,
+            checksums: HashMap::new(),
+            deletion_grace_period_ns: 0,
+            trash: HashMap::new(),
+            allowed_accounts: Vec::new(),
+            allowlist_enforced: false,
+            emergency_account: None,
+            emergency_consents: HashMap::new(),
+            teams: HashMap::new(),
+            site_rules: HashMap::new(),
+            pending_deletions: HashMap::new(),
+            warn_weak_passwords: false,
+            strength_guarded: Vec::new(),
+            next_creation_seq: HashMap::new(),
+            key_fingerprints: HashMap::new(),
+            default_identifiers: HashMap::new(),
+            entropy_mode: EntropyMode::BlockchainRandom,
+            entropy_counter: 0,
+            last_write_timestamp: 0,
+            reveal_tokens: HashMap::new(),
+            auto_lock_ns: HashMap::new(),
+            active_at: HashMap::new(),
+        }
+    }
+}
+
+// One key in an export_borsh backup. Shares and history stay behind, since they only make sense
+// in the keychain they were created in.
+#[derive(BorshDeserialize, BorshSerialize, PartialEq, Debug)]
+pub struct KeyBackup {
+    resource: String,
+    identifier: String,
+    enc_password: String,
+    algorithm: String,
+    custom_fields: Vec<(String, String)>,
+    attachments: Vec<String>,
+    tags: Vec<String>,
+    url: String,
+    security_qa: Vec<(String, String)>,
+    backup_codes: Vec<(String, bool)>,
+    notes: String,
+    archived: bool,
+    clipboard_clear_secs: Option<u32>,
+    reminder: Option<String>,
+    two_factor: Option<String>,
+}
+
+// State layout of the first deployed version, before keys carried any metadata
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyKey {
+    identifier: String,
+    enc_password: String,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyKeychain {
+    keys: HashMap<String, HashMap<String, LegacyKey>>,
 }
 
 #[near_bindgen]
 impl Keychain {
+    // Converts the state written by the first deployed version into the current layout. Must be
+    // called by the contract account itself right after deploying the new code.
+    #[init]
+    pub fn migrate() -> Self {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Only the contract account can migrate its state"
+        );
+        let legacy: LegacyKeychain = env::state_read().expect("No state to migrate");
+
+        let mut keychain = Keychain::default();
+        for (account_id, record) in legacy.keys {
+            // Legacy keys carry no creation order, so resource order stands in for it
+            let mut legacy_keys: Vec<(String, LegacyKey)> = record.into_iter().collect();
+            legacy_keys.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let mut record = HashMap::new();
+            for (seq, (resource, legacy_key)) in legacy_keys.into_iter().enumerate() {
+                let mut key = Key::new(legacy_key.identifier, legacy_key.enc_password);
+                key.creation_seq = seq as u64;
+                record.insert(resource, key);
+            }
+            keychain.next_creation_seq.insert(account_id.clone(), record.len() as u64);
+            keychain.keys.insert(account_id.clone(), record);
+            keychain.update_checksum(&account_id);
+        }
+        keychain.touch();
+        keychain
+    }
+
     pub fn generate_new_password(&mut self, resource: String, identifier: String) {
+        self.touch();
         let account_id = env::signer_account_id();
 
         // Use env::log to record logs permanently to the blockchain!
-        env::log(format!("started executing", ).as_bytes());
+        log("started executing");
 
-        if self.get_password(&account_id, &resource).is_empty()
+        if self.key(&account_id, &resource).is_none()
         {
-            let password_len = 12;
+            let classes = ClassMinimums::default().by_class();
+            validate_generation_params(DEFAULT_PASSWORD_LENGTH, &classes);
+            let password = generate_password(&mut self.next_rng(), DEFAULT_PASSWORD_LENGTH, &classes);
+            warn_if_trivial(&password, &account_id, &identifier, &resource);
+            self.store_key(account_id, resource, identifier, password);
+        }
+
+        // Use env::log to record logs permanently to the blockchain!
+        log("finished executing");
+    }
 
-            let mut selected_set: String = "".to_string();
-            selected_set.push_str(LOWER_CASE_LETTERS);
-            selected_set.push_str(UPPER_CASE_LETTERS);
-            selected_set.push_str(NUMBERS);
-            selected_set.push_str(SPECIAL_CHARS);
+    // Only the account itself or an active grantee of the resource may read it, which needs a signer:
+    // this has to be sent as a transaction rather than a view call. Nothing about the value is
+    // logged, since receipt logs are public and permanent.
+    // The arguments stay &String: near_bindgen deserializes them into owned values of that type
+    #[allow(clippy::ptr_arg)]
+    pub fn get_password(&self, account_id: &String, resource: &String) -> &str {
+        self.assert_can_read(account_id, resource);
+        match self.key(account_id, resource) {
+            Some(key) => &key.enc_password,
+            None => "",
+        }
+    }
 
-            let selected_set_len = selected_set.len();
+    // Generates a password of exactly `length` characters containing at least the given number of
+    // characters from each class, replacing any value already stored for the resource
+    pub fn generate_with_min_classes(&mut self, resource: String, identifier: String, length: u32, minimums: ClassMinimums) {
+        self.touch();
+        let minimums = minimums.by_class();
+        validate_generation_params(length, &minimums);
 
-            let mut rng: StdRng = SeedableRng::from_seed(env::random_seed().try_into().unwrap());
+        let account_id = env::signer_account_id();
+        let password = generate_password(&mut self.next_rng(), length, &minimums);
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_key(account_id, resource, identifier, password);
+    }
 
-            let mut password = "".to_string();
-            for _n in 0..password_len {
-                password.push(selected_set.chars().nth(rng.gen_range(1, selected_set_len)).unwrap());
-            }
+    pub fn generate_with_options(&mut self, resource: String, identifier: String, options: GenerationOptions) {
+        self.touch();
+        let classes = options.character_classes();
+        let classes: Vec<(&str, u32)> = classes.iter().map(|(class, min)| (class.as_str(), *min)).collect();
+        validate_generation_params(options.length, &classes);
+
+        let account_id = env::signer_account_id();
+        let password = generate_password(&mut self.next_rng(), options.length, &classes);
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_generated(account_id, resource, identifier, password, &options);
+    }
+
+    pub fn get_generation_params(&self, account_id: String, resource: String) -> Option<String> {
+        self.key(&account_id, &resource).and_then(|key| key.generation_params.clone())
+    }
 
-            let mut record: HashMap<String, Key> = HashMap::new();
-            record.insert(resource, Key { identifier, enc_password: password });
+    // Regenerates one of the signer's keys with the options its current value was generated with,
+    // avoiding the current value and its history like regenerate_password
+    pub fn regenerate_same_params(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let mut rng = self.next_rng();
+        let key = self.existing_key(&account_id, &resource);
+        let options: GenerationOptions = match &key.generation_params {
+            Some(params) => serde_json::from_str(params).unwrap(),
+            None => panic!("Resource '{}' has no recorded generation parameters", resource),
+        };
+        let identifier = key.identifier.clone();
+        let mut used: Vec<&str> = key.history.iter().map(|(_, value)| value.as_str()).collect();
+        used.push(&key.enc_password);
+
+        let classes = options.character_classes();
+        let classes: Vec<(&str, u32)> = classes.iter().map(|(class, min)| (class.as_str(), *min)).collect();
+        validate_generation_params(options.length, &classes);
+        let password = generate_distinct_password(&mut rng, options.length, &classes, &used, MAX_GENERATION_ATTEMPTS);
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_generated(account_id, resource, identifier, password, &options);
+    }
 
-            self.keys.insert(account_id, record);   
+    // Generates a password of `length` characters for each (resource, identifier) pair, replacing
+    // any stored values. No two passwords of the batch are the same.
+    pub fn generate_batch(&mut self, entries: Vec<(String, String)>, length: u32) {
+        self.touch();
+        assert!(!entries.is_empty(), "At least one entry is required");
+        assert!(entries.len() <= MAX_GENERATION_BATCH, "At most {} keys can be generated at once", MAX_GENERATION_BATCH);
+        for (i, (resource, _)) in entries.iter().enumerate() {
+            assert!(
+                !entries[..i].iter().any(|(other, _)| other.to_lowercase() == resource.to_lowercase()),
+                "Resource '{}' is listed more than once",
+                resource
+            );
         }
+        let classes = ClassMinimums::default().by_class();
+        validate_generation_params(length, &classes);
 
-        // Use env::log to record logs permanently to the blockchain!
-        env::log(format!("finished executing", ).as_bytes());
+        let account_id = env::signer_account_id();
+        let mut rng = self.next_rng();
+        let mut generated: Vec<String> = vec![];
+        for (resource, identifier) in entries {
+            let used: Vec<&str> = generated.iter().map(String::as_str).collect();
+            let password = generate_distinct_password(&mut rng, length, &classes, &used, MAX_GENERATION_ATTEMPTS);
+            warn_if_trivial(&password, &account_id, &identifier, &resource);
+            self.store_key(account_id.clone(), resource, identifier, password.clone());
+            generated.push(password);
+        }
     }
 
-    // `match` is similar to `switch` in other languages; here we use it to default to "Hello" if
-    // self.records.get(&account_id) is not yet defined.
-    // Learn more: https://doc.rust-lang.org/book/ch06-02-match.html#matching-with-optiont
-    pub fn get_password(&self, account_id: &String, resource: &String) -> &str {
-        let result =
-        match self.keys.get(account_id) {
-            Some(record) => match record.get(resource) {
-                Some(key) => &key.enc_password,
-                None => ""
-            },
-            None => "",
+    // Generates candidates until one meets every requirement: starting with a letter if
+    // `must_start_alpha`, no character repeated more than `max_consecutive_same` times in a row
+    // (0 for no limit) and not containing `required_substring_absent` (ignored when empty). Gives up
+    // after MAX_REQUIREMENT_ATTEMPTS candidates.
+    pub fn generate_with_requirements(
+        &mut self,
+        resource: String,
+        identifier: String,
+        length: u32,
+        must_start_alpha: bool,
+        max_consecutive_same: u32,
+        required_substring_absent: String,
+    ) {
+        self.touch();
+        let classes = ClassMinimums::default().by_class();
+        validate_generation_params(length, &classes);
+        let acceptable = |password: &str| {
+            (!must_start_alpha || password.starts_with(|c: char| c.is_ascii_alphabetic()))
+                && (max_consecutive_same == 0 || longest_repeat(password) <= max_consecutive_same)
+                && (required_substring_absent.is_empty() || !password.contains(&required_substring_absent))
         };
 
-        // Use env::log to record logs permanently to the blockchain!
-        env::log(format!("Saving result '{}' for account '{}'", result, account_id,).as_bytes());
+        let mut rng = self.next_rng();
+        let candidate = |rng: &mut StdRng| {
+            let mut chars: Vec<char> = generate_password(rng, length, &classes).chars().collect();
+            // Moving a letter to the front, rather than rejecting, keeps the retries for the rest
+            if must_start_alpha {
+                if let Some(letter) = chars.iter().position(char::is_ascii_alphabetic) {
+                    chars.swap(0, letter);
+                }
+            }
+            chars.into_iter().collect::<String>()
+        };
+        let password = match (0..MAX_REQUIREMENT_ATTEMPTS).map(|_| candidate(&mut rng)).find(|password| acceptable(password)) {
+            Some(password) => password,
+            None => panic!("No password meeting the requirements was found in {} attempts", MAX_REQUIREMENT_ATTEMPTS),
+        };
+        let account_id = env::signer_account_id();
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_key(account_id, resource, identifier, password);
+    }
 
-        result
+    // Generates a password for a site accepting between min_length and max_length characters. It
+    // is as long as allowed, for strength, and has a character of every class the password policy
+    // requires; panics when the policy can't be met within max_length.
+    pub fn generate_bounded(&mut self, resource: String, identifier: String, min_length: u32, max_length: u32) {
+        self.touch();
+        assert!(
+            min_length <= max_length,
+            "Minimum length {} is greater than the maximum length {}",
+            min_length,
+            max_length
+        );
+        let policy = &self.policy;
+        let classes = ClassMinimums {
+            lower: policy.require_lower as u32,
+            upper: policy.require_upper as u32,
+            digits: policy.require_digit as u32,
+            special: policy.require_special as u32,
+        }
+        .by_class();
+        let required: u32 = classes.iter().map(|(_, min)| min).sum();
+        let needed = policy.min_length.max(required);
+        assert!(
+            needed <= max_length,
+            "The password policy needs at least {} characters, more than the site's maximum of {}",
+            needed,
+            max_length
+        );
+        let length = max_length.min(MAX_PASSWORD_LENGTH);
+        assert!(length >= min_length, "Password length must be between {} and {}", MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH);
+        validate_generation_params(length, &classes);
+
+        let account_id = env::signer_account_id();
+        let password = generate_password(&mut self.next_rng(), length, &classes);
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_key(account_id, resource, identifier, password);
     }
-}
 
-/*
- * The rest of this file holds the inline tests for the code above
- * Learn more about Rust tests: https://doc.rust-lang.org/book/ch11-01-writing-tests.html
- *
- * To run from contract directory:
- * cargo test -- --nocapture
- *
- * From project root, to run in combination with frontend tests:
- * yarn test
- *
- */
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
+    // Like generate_with_min_classes, but no character is ever repeated back to back
+    pub fn generate_without_repeats(&mut self, resource: String, identifier: String, length: u32, minimums: ClassMinimums) {
+        self.touch();
+        let minimums = minimums.by_class();
+        validate_generation_params(length, &minimums);
 
-    // mock the context for testing, notice "signer_account_id" that was accessed above from env::
-    fn get_context(input: Vec<u8>, is_view: bool) -> VMContext {
-        VMContext {
-            current_account_id: "alice_near".to_string(),
-            signer_account_id: "bob_near".to_string(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id: "carol_near".to_string(),
-            input,
-            block_index: 0,
-            block_timestamp: 0,
-            account_balance: 0,
-            account_locked_balance: 0,
-            storage_usage: 0,
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10,
-                              11, 12, 13, 14, 15, 16, 17, 18, 19,
-                              20, 21, 22, 23, 24, 25, 26, 27, 28,
-                              29, 30, 31],
-            is_view,
-            output_data_receivers: vec![],
-            epoch_height: 19,
+        let account_id = env::signer_account_id();
+        let password = generate_password_without_repeats(&mut self.next_rng(), length, &minimums, MAX_GENERATION_ATTEMPTS);
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_key(account_id, resource, identifier, password);
+    }
+
+    // Replaces the value of one of the signer's keys with a freshly generated password that differs
+    // from the current value and every value in its history
+    pub fn regenerate_password(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let mut rng = self.next_rng();
+        self.regenerate_key(&mut rng, account_id, resource, DEFAULT_PASSWORD_LENGTH, &ClassMinimums::default().by_class());
+    }
+
+    // Like regenerate_password, choosing the shortest length at which a password with every
+    // character class carries at least `min_bits` bits of estimated entropy
+    pub fn regenerate_min_entropy(&mut self, resource: String, min_bits: u32) {
+        self.touch();
+        let classes = ClassMinimums { lower: 1, upper: 1, digits: 1, special: 1 }.by_class();
+        let pool = validate_generation_params(MAX_PASSWORD_LENGTH, &classes).len();
+        let bits_per_char = (pool as f64).log2();
+        let length = (min_bits as f64 / bits_per_char).ceil() as u32;
+        assert!(
+            length <= MAX_PASSWORD_LENGTH,
+            "{} bits of entropy would take more than the maximum of {} characters",
+            min_bits,
+            MAX_PASSWORD_LENGTH
+        );
+
+        let account_id = env::signer_account_id();
+        let mut rng = self.next_rng();
+        self.regenerate_key(&mut rng, account_id, resource, length.max(MIN_PASSWORD_LENGTH), &classes);
+    }
+
+    // Makes rotate_due regenerate one of the signer's keys every `rotate_every_ns`, or stops it
+    pub fn set_rotation_interval(&mut self, resource: String, rotate_every_ns: Option<u64>) {
+        self.touch();
+        assert!(rotate_every_ns != Some(0), "Rotation interval must be positive");
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.rotate_every_ns = rotate_every_ns);
+    }
+
+    pub fn get_rotation_count(&self, account_id: String, resource: String) -> u32 {
+        self.key(&account_id, &resource).map_or(0, |key| key.rotation_count)
+    }
+
+    pub fn get_rotation_interval(&self, account_id: String, resource: String) -> Option<u64> {
+        self.key(&account_id, &resource).and_then(|key| key.rotate_every_ns)
+    }
+
+    // Crank: regenerates every key of the signer whose rotation interval has passed since its last
+    // update, returning the rotated resources sorted
+    pub fn rotate_due(&mut self) -> Vec<String> {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let now = env::block_timestamp();
+        let mut due: Vec<String> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| key.rotate_every_ns.is_some_and(|interval| key.updated_at.saturating_add(interval) <= now))
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        due.sort();
+
+        // One rng for the whole crank, so keys rotated together don't all get the same value
+        let mut rng = self.next_rng();
+        for resource in &due {
+            self.regenerate_key(&mut rng, account_id.clone(), resource.clone(), DEFAULT_PASSWORD_LENGTH, &ClassMinimums::default().by_class());
         }
+        due
     }
 
-    #[test]
-    fn generate_then_check_password_length() {
-        let context = get_context(vec![], false);
-        testing_env!(context);
-        let mut contract = Keychain::default();
-        contract.generate_new_password("email".to_string(), "bob@email.com".to_string());
-        assert_eq!(
-            12,
-            contract.get_password(&"bob_near".to_string(), &"email".to_string()).len()
+    // Throwaway password for one-time use, returned without being stored so it also works as a
+    // view call. The block's seed is public and shared by every caller of the block, so the
+    // password is seeded from it mixed with `entropy` the caller drew client-side, in either
+    // entropy mode: it never repeats what a stored generation draws.
+    pub fn generate_ephemeral(&self, length: u32, use_special: bool, entropy: String) -> String {
+        assert!(
+            entropy.len() >= MIN_EPHEMERAL_ENTROPY_BYTES,
+            "Entropy must be at least {} bytes",
+            MIN_EPHEMERAL_ENTROPY_BYTES
         );
+        let mut classes = ClassMinimums::default().by_class().to_vec();
+        if !use_special {
+            classes.pop();
+        }
+        validate_generation_params(length, &classes);
+        generate_password(&mut ephemeral_rng(&entropy), length, &classes)
     }
 
-    #[test]
-    fn get_default_key() {
-        let context = get_context(vec![], true);
-        testing_env!(context);
-        let contract = Keychain::default();
-        // this test did not call set_greeting so should return the default "Hello" greeting
-        assert_eq!(
-            "".to_string(),
-            contract.get_password(&"francis.near".to_string(), &"".to_string())
+    // Generates a numeric PIN; some systems reject PINs starting with 0, so that can be disallowed
+    pub fn generate_pin(&mut self, resource: String, identifier: String, digits: u32, allow_leading_zero: bool) {
+        self.touch();
+        assert!(
+            (MIN_PIN_DIGITS..=MAX_PIN_DIGITS).contains(&digits),
+            "A PIN must have between {} and {} digits",
+            MIN_PIN_DIGITS,
+            MAX_PIN_DIGITS
         );
+        let numbers = validate_generation_params(digits, &[(NUMBERS, 0)]);
+        let mut rng = self.next_rng();
+        let mut pin = String::new();
+        for position in 0..digits {
+            let lowest = if position == 0 && !allow_leading_zero { 1 } else { 0 };
+            pin.push(numbers[rng.gen_range(lowest, numbers.len())]);
+        }
+        assert_exact_length(&pin, digits);
+
+        let account_id = env::signer_account_id();
+        warn_if_trivial(&pin, &account_id, &identifier, &resource);
+        self.store_key(account_id, resource, identifier, pin);
+    }
+
+    // Generates a password drawing every character uniformly from the given alphabet. Repeated
+    // characters are dropped so they don't skew the distribution; whitespace and non-ASCII
+    // characters are rejected.
+    pub fn generate_from_alphabet(&mut self, resource: String, identifier: String, length: u32, alphabet: String) {
+        self.touch();
+        if let Some(c) = alphabet.chars().find(|c| !c.is_ascii_graphic()) {
+            panic!("Alphabet may only contain printable ASCII characters other than space, found {:?}", c);
+        }
+        let pool = validate_generation_params(length, &[(&alphabet, 0)]);
+        let mut rng = self.next_rng();
+        let password: String = (0..length).map(|_| pool[rng.gen_range(0, pool.len())]).collect();
+        assert_exact_length(&password, length);
+
+        let account_id = env::signer_account_id();
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_key(account_id, resource, identifier, password);
+    }
+
+    // Stores a client-provided (usually client-side encrypted) value, replacing any existing one.
+    // The replaced value is kept in the key's history.
+    pub fn set_password(&mut self, resource: String, identifier: String, enc_password: String) {
+        self.touch();
+        self.set_password_with_force(resource, identifier, enc_password, false);
+    }
+
+    // Like set_password; `force` replaces a stronger value even with the signer's strength guard on
+    pub fn set_password_with_force(&mut self, resource: String, identifier: String, enc_password: String, force: bool) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.check_not_trivial(&enc_password, &account_id, &identifier, &resource);
+        if let Some(event) = self.weak_password_warning(&enc_password, &account_id, &resource) {
+            log(&event);
+        }
+        self.store_client_value(account_id, resource, identifier, enc_password, force);
+    }
+
+    // Like set_password, tagging the value with the cipher the client encrypted it with
+    pub fn set_password_with_algorithm(&mut self, resource: String, identifier: String, enc_password: String, algorithm: String) {
+        self.touch();
+        assert!(
+            KNOWN_ALGORITHMS.contains(&algorithm.as_str()),
+            "Unknown algorithm '{}', expected one of {}",
+            algorithm,
+            KNOWN_ALGORITHMS.join(", ")
+        );
+        let account_id = env::signer_account_id();
+        self.set_password(resource.clone(), identifier, enc_password);
+        self.update_key(&account_id, &resource, |key| key.algorithm = algorithm);
+    }
+
+    pub fn get_key_metadata(&self, account_id: String, resource: String) -> Option<KeyMetadata> {
+        self.key(&account_id, &resource).map(|key| KeyMetadata {
+            identifier: key.identifier.clone(),
+            updated_at: key.updated_at,
+            algorithm: key.algorithm.clone(),
+            reminder: key.reminder.clone(),
+        })
+    }
+
+    // Returns the value stored `versions_back` rotations ago (0 is the current value)
+    pub fn get_historical_password(&self, account_id: String, resource: String, versions_back: u32) -> Option<String> {
+        self.assert_can_read(&account_id, &resource);
+        let key = self.key(&account_id, &resource)?;
+        let versions_back = versions_back as usize;
+        if versions_back == 0 {
+            return Some(key.enc_password.clone());
+        }
+        key.history.len()
+            .checked_sub(versions_back)
+            .map(|index| key.history[index].1.clone())
+    }
+
+    // Rotation log of one of the signer's keys: (replaced_at, previous value) pairs, oldest first,
+    // holding up to MAX_HISTORY entries
+    pub fn change_history(&self, account_id: String, resource: String) -> Vec<(u64, String)> {
+        let signer = env::signer_account_id();
+        assert_eq!(signer, record_owner(&account_id), "Only '{}' can read its change history", record_owner(&account_id));
+        self.assert_unlocked(&signer);
+        match self.key(&account_id, &resource) {
+            Some(key) => key.history.clone(),
+            None => vec![],
+        }
+    }
+
+    // Called from the source account to agree to a later merge_from by `target_account`
+    pub fn approve_merge(&mut self, target_account: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        assert_ne!(account_id, target_account, "Cannot merge an account into itself");
+        self.merge_approvals.insert(account_id, target_account);
+    }
+
+    // Moves the keys of `source_account` into the signer's keychain. The source account must have
+    // approved the merge first, which proves the signer controls both. When both hold the same
+    // resource, `on_conflict` decides: "keep_mine" keeps the signer's key and drops the source's,
+    // "take_theirs" replaces it with the source's, "skip" leaves the source's key where it is.
+    // Shares granted by the source do not carry over. Returns the number of keys moved.
+    pub fn merge_from(&mut self, source_account: String, on_conflict: String) -> u64 {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let strategy = match on_conflict.as_str() {
+            "keep_mine" | "take_theirs" | "skip" => on_conflict.as_str(),
+            _ => panic!("Unknown conflict strategy '{}', expected keep_mine, take_theirs or skip", on_conflict),
+        };
+        assert_eq!(
+            self.merge_approvals.get(&source_account),
+            Some(&account_id),
+            "'{}' has not approved merging into '{}'",
+            source_account,
+            account_id
+        );
+        self.merge_approvals.remove(&source_account);
+        self.assert_allowed(&account_id);
+
+        // Merged keys count as created now, after every key of the target, keeping their own order
+        let mut source: Vec<(String, Key)> = self.keys.remove(&source_account).unwrap_or_default().into_iter().collect();
+        source.sort_by(|(a, a_key), (b, b_key)| (a_key.creation_seq, a).cmp(&(b_key.creation_seq, b)));
+        let mut left_behind = HashMap::new();
+        let mut unshared = vec![];
+        let mut moved = 0;
+        let next_seq = self.next_creation_seq.entry(account_id.clone()).or_default();
+        let record = self.keys.entry(account_id.clone()).or_default();
+        for (resource, mut key) in source {
+            let existing = stored_resource(record, &resource);
+            if existing.is_some() && strategy == "skip" {
+                left_behind.insert(resource, key);
+                continue;
+            }
+            unshared.push((source_account.clone(), resource.clone(), std::mem::take(&mut key.shares)));
+            if existing.is_none() || strategy == "take_theirs" {
+                key.creation_seq = *next_seq;
+                *next_seq += 1;
+            }
+            match existing {
+                Some(_) if strategy == "keep_mine" => {}
+                Some(existing) => {
+                    let replaced = record.remove(&existing).unwrap();
+                    unshared.push((account_id.clone(), existing, replaced.shares));
+                    record.insert(resource, key);
+                    moved += 1;
+                }
+                None => {
+                    record.insert(resource, key);
+                    moved += 1;
+                }
+            }
+        }
+        assert_credential_cap(self.credential_count(&account_id), self.max_credentials_per_account);
+        if !left_behind.is_empty() {
+            self.keys.insert(source_account.clone(), left_behind);
+        }
+        for (owner, resource, shares) in unshared {
+            self.unindex_shares(&owner, &resource, &shares);
+        }
+        self.update_checksum(&account_id);
+        self.update_checksum(&source_account);
+        moved
+    }
+
+    pub fn set_strict_policy(&mut self, enabled: bool) {
+        self.touch();
+        self.assert_owner();
+        self.strict_policy = enabled;
+    }
+
+    pub fn is_strict_policy(&self) -> bool {
+        self.strict_policy
+    }
+
+    // Switching to DeterministicCounter makes every generated password predictable, so it is
+    // logged as a warning
+    pub fn set_entropy_mode(&mut self, mode: EntropyMode) {
+        self.touch();
+        self.assert_owner();
+        if mode == EntropyMode::DeterministicCounter {
+            log_event("insecure_entropy_mode", json!({ "mode": mode }));
+        }
+        self.entropy_mode = mode;
+    }
+
+    pub fn get_entropy_mode(&self) -> EntropyMode {
+        self.entropy_mode
+    }
+
+    pub fn set_warn_weak_passwords(&mut self, enabled: bool) {
+        self.touch();
+        self.assert_owner();
+        self.warn_weak_passwords = enabled;
+    }
+
+    pub fn is_warn_weak_passwords(&self) -> bool {
+        self.warn_weak_passwords
+    }
+
+    pub fn set_max_credentials_per_account(&mut self, max_credentials: u64) {
+        self.touch();
+        self.assert_owner();
+        self.max_credentials_per_account = max_credentials;
+    }
+
+    pub fn get_max_credentials_per_account(&self) -> u64 {
+        self.max_credentials_per_account
+    }
+
+    pub fn add_allowed_account(&mut self, account_id: String) {
+        self.touch();
+        self.assert_owner();
+        if !self.allowed_accounts.contains(&account_id) {
+            self.allowed_accounts.push(account_id);
+        }
+    }
+
+    pub fn remove_allowed_account(&mut self, account_id: String) {
+        self.touch();
+        self.assert_owner();
+        self.allowed_accounts.retain(|allowed| *allowed != account_id);
+    }
+
+    pub fn get_allowed_accounts(&self) -> Vec<String> {
+        self.allowed_accounts.clone()
+    }
+
+    // While enforced, only allowlisted accounts can store or change keys; reads stay open
+    pub fn set_allowlist_enforced(&mut self, enforced: bool) {
+        self.touch();
+        self.assert_owner();
+        self.allowlist_enforced = enforced;
+    }
+
+    pub fn is_allowlist_enforced(&self) -> bool {
+        self.allowlist_enforced
+    }
+
+    // The owner can't name itself, and naming any other account gives it nothing until key holders
+    // consent to that account through consent_to_emergency_access: no owner method may give access
+    // to key material
+    pub fn set_emergency_account(&mut self, account_id: Option<String>) {
+        self.touch();
+        self.assert_owner();
+        assert!(account_id.as_ref() != Some(&self.owner_id), "The owner cannot be the emergency account");
+        self.emergency_account = account_id;
+    }
+
+    pub fn get_emergency_account(&self) -> Option<String> {
+        self.emergency_account.clone()
+    }
+
+    // Lets `emergency_account` break glass on the signer's keys while the owner keeps it as the
+    // emergency account. Naming it explicitly means the owner swapping in another account doesn't
+    // carry the consent over. None withdraws it.
+    pub fn consent_to_emergency_access(&mut self, emergency_account: Option<String>) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        match emergency_account {
+            Some(emergency_account) => {
+                assert_ne!(emergency_account, account_id, "An account cannot be its own emergency account");
+                self.emergency_consents.insert(account_id, emergency_account);
+            }
+            None => {
+                self.emergency_consents.remove(&account_id);
+            }
+        }
+    }
+
+    pub fn get_emergency_consent(&self, account_id: String) -> Option<String> {
+        self.emergency_consents.get(&account_id).cloned()
+    }
+
+    pub fn set_password_policy(&mut self, policy: PasswordPolicy) {
+        self.touch();
+        self.assert_owner();
+        self.policy = policy;
+    }
+
+    pub fn get_password_policy(&self) -> PasswordPolicy {
+        self.policy.clone()
+    }
+
+    // Registers the rules of a site; they also apply to its subdomains
+    pub fn set_site_rules(&mut self, domain: String, rules: SiteRules) {
+        self.touch();
+        self.assert_owner();
+        self.site_rules.insert(domain.to_lowercase(), rules);
+    }
+
+    pub fn remove_site_rules(&mut self, domain: String) {
+        self.touch();
+        self.assert_owner();
+        self.site_rules.remove(&domain.to_lowercase());
+    }
+
+    // Checks a stored password against the registered rules of the site in its url. Keys without a
+    // url, or for a site without rules, are compliant. The result describes the value, so only
+    // accounts that can read the key may ask, which has to be done in a transaction.
+    pub fn validate_against_domain(&self, account_id: String, resource: String) -> (bool, Vec<String>) {
+        self.assert_can_read(&account_id, &resource);
+        let key = self.existing_key(&account_id, &resource);
+        let reasons = match self.rules_for_url(&key.url) {
+            Some(rules) => rules.violations(&key.enc_password),
+            None => vec![],
+        };
+        (reasons.is_empty(), reasons)
+    }
+
+    // Checks a candidate password against the current policy without storing anything
+    pub fn check_compliance(&self, candidate_password: String) -> (bool, Vec<String>) {
+        let reasons = self.policy.violations(&candidate_password);
+        (reasons.is_empty(), reasons)
+    }
+
+    // First phase of a guarded deletion: the key stays readable until confirm_delete is called
+    // within DELETE_CONFIRMATION_WINDOW_NS. Requesting again restarts the window.
+    pub fn request_delete(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.existing_key(&account_id, &resource);
+        let resource = self.resource_name(&account_id, resource);
+        let deadline = env::block_timestamp() + DELETE_CONFIRMATION_WINDOW_NS;
+        self.pending_deletions.entry(account_id).or_default().insert(resource, deadline);
+    }
+
+    pub fn confirm_delete(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let resource = self.resource_name(&account_id, resource);
+        let deadline = match self.pending_deletions.get(&account_id).and_then(|pending| pending.get(&resource)) {
+            Some(deadline) => *deadline,
+            None => panic!("No deletion was requested for resource '{}'", resource),
+        };
+        assert!(env::block_timestamp() <= deadline, "The deletion request for resource '{}' has expired", resource);
+        self.delete_key(account_id, resource);
+    }
+
+    // Like set_password, storing the key in one of the signer's vaults; "" means the default vault
+    pub fn set_password_in_vault(&mut self, vault: String, resource: String, identifier: String, enc_password: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.check_not_trivial(&enc_password, &account_id, &identifier, &resource);
+        self.store_client_value(vault_record_id(&account_id, &vault), resource, identifier, enc_password, false);
+    }
+
+    pub fn get_password_in_vault(&self, account_id: String, vault: String, resource: String) -> Option<String> {
+        let record_id = vault_record_id(&account_id, &vault);
+        self.assert_can_read(&record_id, &resource);
+        self.key(&record_id, &resource).map(|key| key.enc_password.clone())
+    }
+
+    pub fn delete_password_in_vault(&mut self, vault: String, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.delete_key(vault_record_id(&account_id, &vault), resource);
+    }
+
+    // Moves the listed keys of the signer from one vault to another, keeping their metadata and
+    // shares; resources missing from the source vault are skipped. Nothing is moved if any of them
+    // already exists in the destination. Returns the number of keys moved.
+    pub fn move_to_vault(&mut self, resources: Vec<String>, source_vault: String, dest_vault: String) -> u64 {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let source = vault_record_id(&account_id, &source_vault);
+        let dest = vault_record_id(&account_id, &dest_vault);
+        assert_ne!(source, dest, "Source and destination vault are the same");
+        self.assert_allowed(&source);
+
+        let mut names: Vec<String> = match self.keys.get(&source) {
+            Some(record) => resources.iter().filter_map(|resource| stored_resource(record, resource)).collect(),
+            None => vec![],
+        };
+        names.sort();
+        names.dedup();
+        for name in &names {
+            assert!(self.key(&dest, name).is_none(), "Resource '{}' already exists in vault '{}'", name, dest_vault);
+        }
+
+        for name in &names {
+            let key = self.keys.get_mut(&source).unwrap().remove(name).unwrap();
+            self.unindex_shares(&source, name, &key.shares);
+            for share in &key.shares {
+                self.index_share(share.grantee.clone(), &dest, name);
+            }
+            self.clear_pending_deletion(&source, name);
+            self.keys.entry(dest.clone()).or_default().insert(name.clone(), key);
+        }
+        if self.keys.get(&source).is_some_and(|record| record.is_empty()) {
+            self.keys.remove(&source);
+        }
+        self.update_checksum(&source);
+        self.update_checksum(&dest);
+        names.len() as u64
+    }
+
+    // Names of the account's vaults holding at least one key, sorted
+    pub fn list_vaults(&self, account_id: String) -> Vec<String> {
+        let prefix = format!("{}{}", account_id, VAULT_SEPARATOR);
+        let mut vaults: Vec<String> = self.keys.keys()
+            .filter_map(|record_id| {
+                if *record_id == account_id {
+                    Some(DEFAULT_VAULT.to_string())
+                } else {
+                    record_id.strip_prefix(&prefix).map(str::to_string)
+                }
+            })
+            .collect();
+        vaults.sort();
+        vaults
+    }
+
+    // Creates a team owned by the signer, who is also its first member
+    pub fn create_team(&mut self, team_id: String) {
+        self.touch();
+        assert!(
+            !team_id.is_empty() && team_id.len() <= MAX_TEAM_ID_BYTES,
+            "Team id must be non-empty and at most {} bytes",
+            MAX_TEAM_ID_BYTES
+        );
+        assert!(!self.teams.contains_key(&team_id), "Team '{}' already exists", team_id);
+        let account_id = env::signer_account_id();
+        self.teams.insert(team_id, Team { owner: account_id.clone(), members: vec![account_id] });
+    }
+
+    pub fn add_team_member(&mut self, team_id: String, account_id: String) {
+        self.touch();
+        let team = self.team_mut_as_owner(&team_id);
+        if !team.members.contains(&account_id) {
+            team.members.push(account_id);
+        }
+    }
+
+    pub fn remove_team_member(&mut self, team_id: String, account_id: String) {
+        self.touch();
+        let team = self.team_mut_as_owner(&team_id);
+        assert!(account_id != team.owner, "The owner of team '{}' cannot be removed", team_id);
+        team.members.retain(|member| *member != account_id);
+    }
+
+    pub fn get_team_members(&self, team_id: String) -> Vec<String> {
+        match self.teams.get(&team_id) {
+            Some(team) => team.members.clone(),
+            None => vec![],
+        }
+    }
+
+    // Like set_password, storing the key in a team the signer is a member of
+    pub fn set_team_password(&mut self, team_id: String, resource: String, identifier: String, enc_password: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.assert_team_member(&team_id, &account_id);
+        self.check_not_trivial(&enc_password, &account_id, &identifier, &resource);
+        self.store_client_value(team_record_id(&team_id), resource, identifier, enc_password, false);
+    }
+
+    pub fn get_team_password(&self, team_id: String, resource: String) -> Option<String> {
+        let account_id = env::signer_account_id();
+        self.assert_team_member(&team_id, &account_id);
+        self.assert_unlocked(&account_id);
+        self.key(&team_record_id(&team_id), &resource).map(|key| key.enc_password.clone())
+    }
+
+    // Removes one of the signer's keys and revokes its shares. With a deletion grace period the key
+    // is moved to the trash instead, hidden from every read but restorable until the period is over.
+    pub fn delete_password(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.delete_key(account_id, resource);
+    }
+
+    // Brings a soft-deleted key back while its grace period lasts
+    pub fn restore_password(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.assert_allowed(&account_id);
+        self.purge_expired_trash(&account_id);
+        let removed = self.trash.get_mut(&account_id).and_then(|trashed| {
+            let stored = stored_resource(trashed, &resource)?;
+            trashed.remove(&stored).map(|(key, _)| (stored, key))
+        });
+        let (resource, key) = match removed {
+            Some(entry) => entry,
+            None => panic!("No deleted key to restore for resource '{}'", resource),
+        };
+        if self.trash[&account_id].is_empty() {
+            self.trash.remove(&account_id);
+        }
+        assert!(self.key(&account_id, &resource).is_none(), "Resource '{}' already exists", resource);
+        assert_credential_cap(self.credential_count(&account_id) + 1, self.max_credentials_per_account);
+
+        self.keys.entry(account_id.clone()).or_default().insert(resource, key);
+        self.update_checksum(&account_id);
+    }
+
+    // Permanently removes the signer's soft-deleted keys whose grace period is over
+    pub fn purge_deleted(&mut self) -> u64 {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.purge_expired_trash(&account_id)
+    }
+
+    // Reclaims storage held by stale data in all of the signer's vaults: soft-deleted keys past their
+    // grace period and expired shares along with their reverse index entries. History needs no
+    // pruning, store_key already caps it at MAX_HISTORY. Returns an estimate of the bytes freed,
+    // from the Borsh size of the removed data.
+    pub fn compact(&mut self) -> u64 {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let mut record_ids: Vec<String> = self.keys.keys().chain(self.trash.keys())
+            .filter(|record_id| record_owner(record_id) == account_id)
+            .cloned()
+            .collect();
+        record_ids.sort();
+        record_ids.dedup();
+
+        let mut reclaimed = 0;
+        for record_id in record_ids {
+            let before = self.record_size(&record_id);
+            self.purge_expired_trash(&record_id);
+            let mut expired = vec![];
+            if let Some(record) = self.keys.get_mut(&record_id) {
+                for (resource, key) in record.iter_mut() {
+                    let (active, stale): (Vec<Share>, Vec<Share>) = key.shares.drain(..).partition(|share| share.is_active());
+                    key.shares = active;
+                    if !stale.is_empty() {
+                        expired.push((resource.clone(), stale));
+                    }
+                }
+            }
+            for (resource, shares) in &expired {
+                self.unindex_shares(&record_id, resource, shares);
+            }
+            self.update_checksum(&record_id);
+            reclaimed += before.saturating_sub(self.record_size(&record_id));
+        }
+        reclaimed
+    }
+
+    pub fn set_deletion_grace_period(&mut self, grace_period_ns: u64) {
+        self.touch();
+        self.assert_owner();
+        self.deletion_grace_period_ns = grace_period_ns;
+    }
+
+    pub fn get_deletion_grace_period(&self) -> u64 {
+        self.deletion_grace_period_ns
+    }
+
+    // Rewrites identifiers like "name@old_domain" of the signer's keys to "name@new_domain" and
+    // returns how many were changed. Domains are matched case-insensitively.
+    pub fn replace_identifier_domain(&mut self, old_domain: String, new_domain: String) -> u64 {
+        self.touch();
+        for domain in &[&old_domain, &new_domain] {
+            assert!(!domain.is_empty() && !domain.contains('@'), "'{}' is not a valid email domain", domain);
+        }
+        let account_id = env::signer_account_id();
+        self.assert_allowed(&account_id);
+        let suffix = format!("@{}", old_domain.to_lowercase());
+
+        let mut changed = 0;
+        if let Some(record) = self.keys.get_mut(&account_id) {
+            for (resource, key) in record.iter_mut() {
+                let split = key.identifier.len().saturating_sub(suffix.len());
+                if key.identifier.is_char_boundary(split) && key.identifier[split..].to_lowercase() == suffix {
+                    let identifier = format!("{}@{}", &key.identifier[..split], new_domain);
+                    assert_key_names(resource, &identifier);
+                    key.identifier = identifier;
+                    changed += 1;
+                }
+            }
+        }
+        if changed > 0 {
+            self.update_checksum(&account_id);
+        }
+        changed
+    }
+
+    // Recomputes the account's checksum and compares it with the one stored on the last write.
+    // A mismatch means the state was corrupted or partially written.
+    pub fn verify_integrity(&self, account_id: String) -> bool {
+        match self.keys.get(&account_id) {
+            Some(record) => self.checksums.get(&account_id) == Some(&record_checksum(record)),
+            None => !self.checksums.contains_key(&account_id),
+        }
+    }
+
+    // Crate version and the FEATURES this deployment supports
+    pub fn version_info(&self) -> (String, Vec<String>) {
+        (env!("CARGO_PKG_VERSION").to_string(), FEATURES.iter().map(|feature| feature.to_string()).collect())
+    }
+
+    // Liveness signal for dashboards: when a call last changed contract state, reads aside
+    pub fn last_activity(&self) -> u64 {
+        self.last_write_timestamp
+    }
+
+    // Unlike get_password, tells a missing resource (None) apart from a stored empty value
+    pub fn get_password_opt(&self, account_id: String, resource: String) -> Option<String> {
+        self.assert_can_read(&account_id, &resource);
+        self.key(&account_id, &resource).map(|key| key.enc_password.clone())
+    }
+
+    // Like get_password_opt, recording the time of the read for most_recently_accessed. Views
+    // can't write state, so only reads sent as a transaction through here are tracked.
+    pub fn access_password(&mut self, account_id: String, resource: String) -> Option<String> {
+        self.assert_can_read(&account_id, &resource);
+        self.keep_unlocked();
+        let resource = self.resource_name(&account_id, resource);
+        let key = self.keys.get_mut(&account_id).and_then(|record| record.get_mut(&resource))?;
+        key.last_accessed = Some(env::block_timestamp());
+        let password = key.enc_password.clone();
+        self.update_checksum(&account_id);
+        Some(password)
+    }
+
+    // Up to `limit` of the account's keys read through access_password, most recent first, with
+    // the time of their latest read
+    pub fn most_recently_accessed(&self, account_id: String, limit: u64) -> Vec<(String, u64)> {
+        let mut accessed: Vec<(String, u64)> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter_map(|(resource, key)| key.last_accessed.map(|at| (resource.clone(), at)))
+                .collect(),
+            None => vec![],
+        };
+        accessed.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        accessed.truncate(limit as usize);
+        accessed
+    }
+
+    // Records the fingerprint of the key the signer's client encrypts with; an empty one clears it.
+    // State is public, so only a salted hash is kept: no method returns the fingerprint, but
+    // anyone can still check a guess against the hash, which is why it needs to be unguessable.
+    pub fn set_key_fingerprint(&mut self, key_fingerprint: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        if key_fingerprint.is_empty() {
+            self.key_fingerprints.remove(&account_id);
+        } else {
+            let salt = to_hex(&env::sha256(&[env::random_seed(), account_id.as_bytes().to_vec()].concat()));
+            let hash = salted_fingerprint(&salt, &key_fingerprint);
+            self.key_fingerprints.insert(account_id, (salt, hash));
+        }
+    }
+
+    // Like get_password_opt, but only returns the value if `key_fingerprint` matches the account's
+    // recorded fingerprint, so a client holding the wrong key doesn't fetch ciphertext it can't use
+    pub fn get_password_verified(&self, account_id: String, resource: String, key_fingerprint: String) -> Option<String> {
+        self.assert_can_read(&account_id, &resource);
+        match self.key_fingerprints.get(record_owner(&account_id)) {
+            Some((salt, hash)) if *hash == salted_fingerprint(salt, &key_fingerprint) => {}
+            _ => return None,
+        }
+        self.key(&account_id, &resource).map(|key| key.enc_password.clone())
+    }
+
+    // Counts the account's credentials by how long ago they were last updated:
+    // [fresh (< 30 days), < 90 days, < 1 year, older]
+    pub fn age_histogram(&self, account_id: String) -> [u64; 4] {
+        let mut buckets = [0u64; 4];
+        let now = env::block_timestamp();
+
+        if let Some(record) = self.keys.get(&account_id) {
+            for key in record.values() {
+                let age = now.saturating_sub(key.updated_at);
+                let bucket = if age < AGE_FRESH_NS {
+                    0
+                } else if age < AGE_QUARTER_NS {
+                    1
+                } else if age < AGE_YEAR_NS {
+                    2
+                } else {
+                    3
+                };
+                buckets[bucket] += 1;
+            }
+        }
+
+        buckets
+    }
+
+    // The account's resources except archived ones, sorted
+    pub fn list_resources(&self, account_id: String) -> Vec<String> {
+        self.resources_where(&account_id, |key| !key.archived)
+    }
+
+    pub fn list_archived(&self, account_id: String) -> Vec<String> {
+        self.resources_where(&account_id, |key| key.archived)
+    }
+
+    // Archiving hides a key from list_resources; it stays readable and keeps its shares
+    pub fn archive_resource(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.archived = true);
+    }
+
+    pub fn unarchive_resource(&mut self, resource: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.archived = false);
+    }
+
+    // The account's resources in the order they were first stored, which unlike timestamps never
+    // ties within a block
+    pub fn list_by_creation(&self, account_id: String) -> Vec<String> {
+        let mut created: Vec<(u64, &String)> = match self.keys.get(&account_id) {
+            Some(record) => record.iter().map(|(resource, key)| (key.creation_seq, resource)).collect(),
+            None => vec![],
+        };
+        created.sort();
+        created.into_iter().map(|(_, resource)| resource.clone()).collect()
+    }
+
+    // Returns up to `limit` of the account's resources with their last update time, most recent first
+    pub fn list_recent(&self, account_id: String, limit: u64) -> Vec<(String, u64)> {
+        let mut recent: Vec<(String, u64)> = match self.keys.get(&account_id) {
+            Some(record) => record.iter().map(|(resource, key)| (resource.clone(), key.updated_at)).collect(),
+            None => vec![],
+        };
+        recent.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        recent.truncate(limit as usize);
+        recent
+    }
+
+    // (oldest, newest) last update time across the account's keys, or None without any keys
+    pub fn timestamp_bounds(&self, account_id: String) -> Option<(u64, u64)> {
+        let record = self.keys.get(&account_id)?;
+        let oldest = record.values().map(|key| key.updated_at).min()?;
+        let newest = record.values().map(|key| key.updated_at).max()?;
+        Some((oldest, newest))
+    }
+
+    // Adds a custom field to one of the signer's keys, or replaces its value if the field exists
+    pub fn set_custom_field(&mut self, resource: String, field: String, value: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        assert!(!field.is_empty(), "Custom field name must not be empty");
+
+        self.update_key(&account_id, &resource, |key| {
+            let mut fields: Vec<(String, String)> = key.custom_fields.iter()
+                .filter(|(name, _)| *name != field)
+                .cloned()
+                .collect();
+            fields.push((field, value));
+            assert_custom_fields(&fields);
+            key.custom_fields = fields;
+        });
+    }
+
+    // Lists the account's resources whose stored password is estimated to carry fewer than
+    // `min_bits` bits of entropy, so they can be regenerated
+    pub fn audit_entropy(&self, account_id: String, min_bits: u32) -> Vec<String> {
+        self.assert_own_account(&account_id);
+        let mut weak: Vec<String> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| estimate_entropy_bits(&key.enc_password) < min_bits as f64)
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        weak.sort();
+        weak
+    }
+
+    // Owner audit: groups of (account, resource) pairs across all accounts that store the same
+    // value, each group and the list sorted. Only names are returned, never the values. Values are
+    // compared as stored, so this only finds reuse of plaintext values or of ciphertexts encrypted
+    // deterministically with the same key; the same password encrypted by two clients will not
+    // match. Checking the owner needs a predecessor, so this has to be sent as a transaction.
+    pub fn find_org_reuse(&self) -> Vec<Vec<(String, String)>> {
+        self.assert_owner();
+        let mut by_value: HashMap<&str, Vec<(String, String)>> = HashMap::new();
+        for (account_id, record) in &self.keys {
+            for (resource, key) in record {
+                if !key.enc_password.is_empty() {
+                    by_value.entry(&key.enc_password).or_default().push((account_id.clone(), resource.clone()));
+                }
+            }
+        }
+        let mut groups: Vec<Vec<(String, String)>> = by_value.into_values()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort();
+                group
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    // Safety net for write paths that forget one side of a share: true when every share on a key
+    // has exactly one shared_with entry and every shared_with entry points at such a share. Shares
+    // past their expiry still count, since both sides keep them until they are removed. Checking
+    // the owner needs a predecessor, so this has to be sent as a transaction.
+    pub fn verify_share_indexes(&self) -> bool {
+        self.assert_owner();
+        let forward: HashSet<(&str, &str, &str)> = self.keys.iter()
+            .flat_map(|(record_id, record)| record.iter().map(move |(resource, key)| (record_id, resource, key)))
+            .flat_map(|(record_id, resource, key)| {
+                key.shares.iter().map(move |share| (share.grantee.as_str(), record_id.as_str(), resource.as_str()))
+            })
+            .collect();
+        let mut reverse = HashSet::new();
+        for (grantee, entries) in &self.shared_with {
+            for (owner, resource) in entries {
+                if !reverse.insert((grantee.as_str(), owner.as_str(), resource.as_str())) {
+                    return false;
+                }
+            }
+        }
+        forward == reverse
+    }
+
+    // Owner-only erasure of everything stored for an account: the keys of all its vaults, its
+    // trash, shares it granted or was granted, team memberships and per-account settings. Teams it
+    // owned pass to their longest-standing remaining member, or are erased with their keys when it
+    // was the last one, so a later account of the same name can't claim them. Contract settings
+    // naming it (allowlist, emergency account) are the owner's and stay. Returns the number of keys
+    // erased, trash not included.
+    pub fn erase_account(&mut self, account_id: String) -> u64 {
+        self.touch();
+        self.assert_owner();
+        let mut abandoned_teams = vec![];
+        for (team_id, team) in self.teams.iter_mut() {
+            team.members.retain(|member| *member != account_id);
+            if team.owner == account_id {
+                match team.members.first() {
+                    Some(member) => team.owner = member.clone(),
+                    None => abandoned_teams.push(team_id.clone()),
+                }
+            }
+        }
+        for team_id in &abandoned_teams {
+            self.teams.remove(team_id);
+        }
+
+        let owned = |record_id: &String| {
+            record_owner(record_id) == account_id || abandoned_teams.iter().any(|team_id| *record_id == team_record_id(team_id))
+        };
+        let record_ids: Vec<String> = self.keys.keys().chain(self.trash.keys()).filter(|id| owned(id)).cloned().collect();
+
+        let mut erased = 0;
+        for record_id in &record_ids {
+            if let Some(record) = self.keys.remove(record_id) {
+                erased += record.len() as u64;
+            }
+            self.trash.remove(record_id);
+            self.pending_deletions.remove(record_id);
+            self.update_checksum(record_id);
+        }
+        for entries in self.shared_with.values_mut() {
+            entries.retain(|(owner, _)| !owned(owner));
+        }
+
+        // Shares granted to the account by others
+        for (owner, resource) in self.shared_with.remove(&account_id).unwrap_or_default() {
+            if let Some(key) = self.keys.get_mut(&owner).and_then(|record| record.get_mut(&resource)) {
+                key.shares.retain(|share| share.grantee != account_id);
+                self.update_checksum(&owner);
+            }
+        }
+
+        self.shared_with.retain(|_, entries| !entries.is_empty());
+        self.merge_approvals.retain(|source, target| *source != account_id && *target != account_id);
+        self.default_identifiers.remove(&account_id);
+        self.key_fingerprints.remove(&account_id);
+        self.next_creation_seq.remove(&account_id);
+        self.reveal_tokens.remove(&account_id);
+        self.emergency_consents.remove(&account_id);
+        self.emergency_consents.retain(|_, emergency| *emergency != account_id);
+        self.auto_lock_ns.remove(&account_id);
+        self.active_at.remove(&account_id);
+        self.strength_guarded.retain(|guarded| *guarded != account_id);
+        erased
+    }
+
+    // Groups of the account's resources whose values are the same once trailing digits are dropped,
+    // like "Summer2023" and "Summer2024". The remaining prefix must be at least
+    // NEAR_DUPLICATE_MIN_PREFIX characters, so short values like PINs aren't grouped.
+    pub fn find_near_duplicates(&self, account_id: String) -> Vec<Vec<String>> {
+        self.assert_own_account(&account_id);
+        let mut by_base: HashMap<&str, Vec<String>> = HashMap::new();
+        if let Some(record) = self.keys.get(&account_id) {
+            for (resource, key) in record {
+                let base = key.enc_password.trim_end_matches(|c: char| c.is_ascii_digit());
+                if base.chars().count() >= NEAR_DUPLICATE_MIN_PREFIX {
+                    by_base.entry(base).or_default().push(resource.clone());
+                }
+            }
+        }
+        let mut groups: Vec<Vec<String>> = by_base.into_values()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort();
+                group
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    // Seconds an exhaustive search over the password's character pool and length would take at
+    // GUESSES_PER_SECOND, saturating at u64::MAX for very strong passwords
+    pub fn crack_time_estimate(&self, account_id: String, resource: String) -> u64 {
+        self.assert_can_read(&account_id, &resource);
+        let password = &self.existing_key(&account_id, &resource).enc_password;
+        let length = password.chars().count() as u32;
+        match (charset_size(password) as u128).checked_pow(length) {
+            Some(combinations) => (combinations / GUESSES_PER_SECOND).try_into().unwrap_or(u64::MAX),
+            None => u64::MAX,
+        }
+    }
+
+    // Lists the account's resources whose stored password has fewer than `min_length` characters.
+    // Only meaningful for keys whose value is stored in a readable form; a ciphertext's length says
+    // little about the password it encrypts.
+    pub fn short_passwords(&self, account_id: String, min_length: u32) -> Vec<String> {
+        self.assert_own_account(&account_id);
+        let mut short: Vec<String> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| key.enc_password.chars().count() < min_length as usize)
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        short.sort();
+        short
+    }
+
+    // Strength score of a stored value from 0 to 100, see strength_score. 0 when there is no such key.
+    pub fn password_strength(&self, account_id: String, resource: String) -> u8 {
+        self.assert_can_read(&account_id, &resource);
+        self.key(&account_id, &resource).map_or(0, |key| strength_score(&key.enc_password))
+    }
+
+    // Mean strength score of the account's keys, rounded down; 0 without any keys
+    pub fn average_strength(&self, account_id: String) -> u8 {
+        self.assert_own_account(&account_id);
+        match self.keys.get(&account_id) {
+            Some(record) if !record.is_empty() => {
+                let total: u64 = record.values().map(|key| strength_score(&key.enc_password) as u64).sum();
+                (total / record.len() as u64) as u8
+            }
+            _ => 0,
+        }
+    }
+
+    // Up to `limit` of the account's resources with their strength scores, weakest first and ties
+    // by resource, as a remediation worklist
+    pub fn weakest_first(&self, account_id: String, limit: u64) -> Vec<(String, u8)> {
+        self.assert_own_account(&account_id);
+        let mut scores: Vec<(String, u8)> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .map(|(resource, key)| (resource.clone(), strength_score(&key.enc_password)))
+                .collect(),
+            None => vec![],
+        };
+        scores.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        scores.truncate(limit as usize);
+        scores
+    }
+
+    // Share of the account's keys that a distinct value accounts for, as a percentage: distinct
+    // values over keys, so 100 means no value is reused. 100 without any keys.
+    pub fn reuse_ratio(&self, account_id: String) -> u8 {
+        self.assert_own_account(&account_id);
+        match self.keys.get(&account_id) {
+            Some(record) if !record.is_empty() => {
+                let distinct: HashSet<&String> = record.values().map(|key| &key.enc_password).collect();
+                (distinct.len() * 100 / record.len()) as u8
+            }
+            _ => 100,
+        }
+    }
+
+    // Whether a stored value looks like a date or a phone number, see personal_pattern
+    pub fn has_personal_pattern(&self, account_id: String, resource: String) -> bool {
+        self.assert_can_read(&account_id, &resource);
+        self.key(&account_id, &resource).is_some_and(|key| personal_pattern(&key.enc_password))
+    }
+
+    // Presence of (lower, upper, digit, special) characters in a stored value, for strength badges.
+    // All false when there is no such key. Like every property of a value, only accounts that can
+    // read the key may query it.
+    pub fn password_classes(&self, account_id: String, resource: String) -> (bool, bool, bool, bool) {
+        self.assert_can_read(&account_id, &resource);
+        match self.key(&account_id, &resource) {
+            Some(key) => classes_present(&key.enc_password),
+            None => (false, false, false, false),
+        }
+    }
+
+    // Whether a stored value contains a keyboard row stretch ("qwer", "lkjh") or a run of consecutive
+    // characters ("abcd", "9876") of WEAK_PATTERN_RUN or more, ignoring case
+    pub fn has_weak_pattern(&self, account_id: String, resource: String) -> bool {
+        self.assert_can_read(&account_id, &resource);
+        self.key(&account_id, &resource).is_some_and(|key| weak_pattern(&key.enc_password))
+    }
+
+    // Lists the account's resources whose stored password contains the local part of the key's
+    // identifier ("bob" for "bob@email.com"), ignoring case. Only meaningful for keys whose value
+    // is stored in a readable form; ciphertexts will practically never match.
+    pub fn flag_username_based(&self, account_id: String) -> Vec<String> {
+        self.assert_own_account(&account_id);
+        let mut flagged: Vec<String> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| contains_username(&key.enc_password, &key.identifier))
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        flagged.sort();
+        flagged
+    }
+
+    // Resources of the account stored with the given identifier, ignoring case
+    pub fn find_by_identifier(&self, account_id: String, identifier: String) -> Vec<String> {
+        let identifier = identifier.to_lowercase();
+        let mut found: Vec<String> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| key.identifier.to_lowercase() == identifier)
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        found.sort();
+        found
+    }
+
+    // Grants every listed account read access to one of the signer's keys. Granting again to an
+    // account that already has access replaces its expiry.
+    pub fn share_with_many(&mut self, resource: String, grantees: Vec<String>, expires_at: Option<u64>) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let resource = self.resource_name(&account_id, resource);
+        assert!(!grantees.is_empty(), "At least one grantee is required");
+        assert!(grantees.len() <= MAX_SHARE_BATCH, "At most {} grantees can be added at once", MAX_SHARE_BATCH);
+        assert!(!grantees.contains(&account_id), "Cannot share a key with its owner");
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at > env::block_timestamp(), "Share expiry must be in the future");
+        }
+
+        self.update_key(&account_id, &resource, |key| {
+            key.shares.retain(|share| !grantees.contains(&share.grantee));
+            for grantee in &grantees {
+                if !key.shares.iter().any(|share| share.grantee == *grantee) {
+                    key.shares.push(Share { grantee: grantee.clone(), expires_at });
+                }
+            }
+        });
+        for grantee in grantees {
+            self.index_share(grantee, &account_id, &resource);
+        }
+    }
+
+    // Generates a password of `length` characters like generate_with_min_classes with the default
+    // minimums, then shares it with the grantee
+    pub fn generate_and_share(&mut self, resource: String, identifier: String, length: u32, grantee: String, expires_at: Option<u64>) {
+        self.touch();
+        self.generate_with_min_classes(resource.clone(), identifier, length, ClassMinimums::default());
+        self.share_with_many(resource, vec![grantee], expires_at);
+    }
+
+    // Like generate_and_share for a client-provided (usually encrypted) value
+    pub fn set_and_share(&mut self, resource: String, identifier: String, enc_password: String, grantee: String, expires_at: Option<u64>) {
+        self.touch();
+        self.set_password(resource.clone(), identifier, enc_password);
+        self.share_with_many(resource, vec![grantee], expires_at);
+    }
+
+    // Lists the accounts that currently have access to one of the signer's keys
+    pub fn list_shares(&self, resource: String) -> Vec<String> {
+        let account_id = env::signer_account_id();
+        let mut grantees: Vec<String> = match self.key(&account_id, &resource) {
+            Some(key) => key.shares.iter()
+                .filter(|share| share.is_active())
+                .map(|share| share.grantee.clone())
+                .collect(),
+            None => vec![],
+        };
+        grantees.sort();
+        grantees
+    }
+
+    // The signer's active shares that expire within `within_ns`, of one resource or of all of them,
+    // as (resource, grantee, expires_at) sorted by expiry
+    pub fn expiring_shares(&self, resource: Option<String>, within_ns: u64) -> Vec<(String, String, u64)> {
+        let account_id = env::signer_account_id();
+        let deadline = env::block_timestamp().saturating_add(within_ns);
+        let resource = resource.map(|resource| self.resource_name(&account_id, resource));
+        let mut expiring: Vec<(String, String, u64)> = vec![];
+        if let Some(record) = self.keys.get(&account_id) {
+            for (name, key) in record {
+                if resource.as_ref().is_some_and(|resource| resource != name) {
+                    continue;
+                }
+                for share in key.shares.iter().filter(|share| share.is_active()) {
+                    if let Some(expires_at) = share.expires_at.filter(|expires_at| *expires_at <= deadline) {
+                        expiring.push((name.clone(), share.grantee.clone(), expires_at));
+                    }
+                }
+            }
+        }
+        expiring.sort_by(|a, b| (a.2, &a.0, &a.1).cmp(&(b.2, &b.0, &b.1)));
+        expiring
+    }
+
+    // Replaces the value of a shared key and revokes every share, emitting a `shares_revoked` event
+    // so grantees can be told to request access again. Returns the accounts that lost access.
+    pub fn rotate_and_revoke_shares(&mut self, resource: String, new_ciphertext: String) -> Vec<String> {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let resource = self.resource_name(&account_id, resource);
+        let identifier = self.existing_key(&account_id, &resource).identifier.clone();
+        self.set_password(resource.clone(), identifier, new_ciphertext);
+
+        let shares = self.update_key(&account_id, &resource, |key| std::mem::take(&mut key.shares));
+        self.unindex_shares(&account_id, &resource, &shares);
+        let mut revoked: Vec<String> = shares.iter()
+            .filter(|share| share.is_active())
+            .map(|share| share.grantee.clone())
+            .collect();
+        revoked.sort();
+
+        log(&shares_revoked_event(&account_id, &resource, &revoked));
+        revoked
+    }
+
+    // Offboarding: revokes every share the signer granted to `grantee`, in all of the signer's
+    // vaults, emitting a `shares_revoked` event per key. Returns the number of shares revoked.
+    pub fn revoke_all_for_grantee(&mut self, grantee: String) -> u64 {
+        self.touch();
+        let account_id = env::signer_account_id();
+        let entries: Vec<(String, String)> = match self.shared_with.get(&grantee) {
+            Some(entries) => entries.iter().filter(|(owner, _)| record_owner(owner) == account_id).cloned().collect(),
+            None => vec![],
+        };
+
+        let mut revoked = 0;
+        for (owner, resource) in entries {
+            if self.key(&owner, &resource).is_some() {
+                let removed = self.update_key(&owner, &resource, |key| {
+                    let before = key.shares.len();
+                    key.shares.retain(|share| share.grantee != grantee);
+                    before - key.shares.len()
+                });
+                if removed > 0 {
+                    log(&shares_revoked_event(&owner, &resource, std::slice::from_ref(&grantee)));
+                }
+                revoked += removed as u64;
+            }
+            self.unindex_shares(&owner, &resource, &[Share { grantee: grantee.clone(), expires_at: None }]);
+        }
+        revoked
+    }
+
+    // Lists the (owner, resource) pairs currently shared with `grantee` by any owner
+    pub fn shared_with_me(&self, grantee: String) -> Vec<(String, String)> {
+        let mut shared: Vec<(String, String)> = match self.shared_with.get(&grantee) {
+            Some(entries) => entries.iter()
+                .filter(|(owner, resource)| {
+                    self.key(owner, resource).is_some_and(|key| {
+                        key.shares.iter().any(|share| share.grantee == grantee && share.is_active())
+                    })
+                })
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        shared.sort();
+        shared
+    }
+
+    // Returns a key that `owner` shared with the signer
+    pub fn get_shared_password(&self, owner: String, resource: String) -> String {
+        let account_id = env::signer_account_id();
+        self.assert_unlocked(&account_id);
+        match self.key(&owner, &resource) {
+            Some(key) if key.shares.iter().any(|share| share.grantee == account_id && share.is_active()) => {
+                key.enc_password.clone()
+            }
+            _ => panic!("Resource '{}' of '{}' is not shared with '{}'", resource, owner, account_id),
+        }
+    }
+
+    // Emergency access: the owner-designated emergency account can read the keys of accounts that
+    // consented to it. Every such read is recorded permanently with a break_glass_access event.
+    pub fn break_glass_read(&self, owner: String, resource: String) -> String {
+        let caller = env::signer_account_id();
+        assert!(
+            self.emergency_account.as_ref() == Some(&caller),
+            "Account '{}' is not the emergency account",
+            caller
+        );
+        assert!(
+            self.emergency_consents.get(record_owner(&owner)) == Some(&caller),
+            "Account '{}' has not consented to emergency access by '{}'",
+            record_owner(&owner),
+            caller
+        );
+        self.assert_unlocked(&caller);
+        let password = self.existing_key(&owner, &resource).enc_password.clone();
+        log(&break_glass_event(&caller, &owner, &resource, env::block_timestamp()));
+        password
+    }
+
+    // Lets whoever holds the token read one of the signer's keys once, within ttl_ns. The token is
+    // picked off-chain and only its hex sha256 is passed in, since contract state is public.
+    // Expired tokens of the signer are dropped along the way.
+    pub fn create_reveal_token(&mut self, resource: String, token_hash: String, ttl_ns: u64) {
+        self.touch();
+        assert!(is_sha256_hex(&token_hash), "Token hash must be a lowercase hex-encoded sha256 digest");
+        let account_id = env::signer_account_id();
+        let resource = self.resource_name(&account_id, resource);
+        self.existing_key(&account_id, &resource);
+        let now = env::block_timestamp();
+        let tokens = self.reveal_tokens.entry(account_id).or_default();
+        tokens.retain(|_, (_, expires_at)| now < *expires_at);
+        assert!(!tokens.contains_key(&token_hash), "Reveal token is already in use");
+        tokens.insert(token_hash, (resource, now.saturating_add(ttl_ns)));
+    }
+
+    // The value a reveal token grants access to. The token is used up even when it has expired
+    // or its key is gone, so None is all any later attempt gets.
+    pub fn redeem_reveal_token(&mut self, owner: String, token: String) -> Option<String> {
+        self.touch();
+        self.assert_unlocked(&env::signer_account_id());
+        let token_hash = to_hex(&env::sha256(token.as_bytes()));
+        let tokens = self.reveal_tokens.get_mut(&owner)?;
+        let (resource, expires_at) = tokens.remove(&token_hash)?;
+        if tokens.is_empty() {
+            self.reveal_tokens.remove(&owner);
+        }
+        if env::block_timestamp() >= expires_at {
+            return None;
+        }
+        self.key(&owner, &resource).map(|key| key.enc_password.clone())
+    }
+
+    // Stores a key under a resource name the frontend already hashed (hex-encoded sha256), so the
+    // plaintext name never reaches contract state. The frontend is responsible for the mapping.
+    pub fn set_by_hash(&mut self, resource_hash: String, identifier: String, enc_password: String) {
+        self.touch();
+        assert_resource_hash(&resource_hash);
+        let account_id = env::signer_account_id();
+        self.check_not_trivial(&enc_password, &account_id, &identifier, &resource_hash);
+        self.store_client_value(account_id, resource_hash, identifier, enc_password, false);
+    }
+
+    pub fn get_by_hash(&self, account_id: String, resource_hash: String) -> String {
+        assert_resource_hash(&resource_hash);
+        self.assert_can_read(&account_id, &resource_hash);
+        match self.key(&account_id, &resource_hash) {
+            Some(key) => key.enc_password.clone(),
+            None => "".to_string(),
+        }
+    }
+
+    // Exports the account's keys as (resource, identifier, enc_password) sorted by resource, along
+    // with the hex sha256 of their canonical (Borsh) serialization for later verification
+    pub fn export_with_hash(&self, account_id: String) -> (Vec<(String, String, String)>, String) {
+        assert_eq!(env::signer_account_id(), account_id, "Only '{}' can export its keys", account_id);
+        self.assert_unlocked(&account_id);
+        let mut entries: Vec<(String, String, String)> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .map(|(resource, key)| (resource.clone(), key.identifier.clone(), key.enc_password.clone()))
+                .collect(),
+            None => vec![],
+        };
+        entries.sort();
+        let hash = snapshot_hash(&entries);
+        (entries, hash)
+    }
+
+    // Compact binary backup of the signer's keys, sorted by resource. Restricted to the account
+    // itself, so it has to be sent as a transaction rather than a view call.
+    pub fn export_borsh(&self, account_id: String) -> Vec<u8> {
+        assert_eq!(env::signer_account_id(), account_id, "Only '{}' can export its keys", account_id);
+        self.assert_unlocked(&account_id);
+        let mut backup: Vec<KeyBackup> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .map(|(resource, key)| KeyBackup {
+                    resource: resource.clone(),
+                    identifier: key.identifier.clone(),
+                    enc_password: key.enc_password.clone(),
+                    algorithm: key.algorithm.clone(),
+                    custom_fields: key.custom_fields.clone(),
+                    attachments: key.attachments.clone(),
+                    tags: key.tags.clone(),
+                    url: key.url.clone(),
+                    security_qa: key.security_qa.clone(),
+                    backup_codes: key.backup_codes.clone(),
+                    notes: key.notes.clone(),
+                    archived: key.archived,
+                    clipboard_clear_secs: key.clipboard_clear_secs,
+                    reminder: key.reminder.clone(),
+                    two_factor: key.two_factor.clone(),
+                })
+                .collect(),
+            None => vec![],
+        };
+        backup.sort_by(|a, b| a.resource.cmp(&b.resource));
+        backup.try_to_vec().unwrap()
+    }
+
+    // Merges an export_borsh backup into the signer's keys. Keys already stored under the same
+    // resource are overwritten, with their previous value kept in history.
+    pub fn import_borsh(&mut self, data: Vec<u8>) {
+        self.touch();
+        let mut backup = match Vec::<KeyBackup>::try_from_slice(&data) {
+            Ok(backup) => backup,
+            Err(_) => panic!("Malformed backup data"),
+        };
+        let account_id = env::signer_account_id();
+        // Every field is held to the limits of its setter, so a crafted backup can't store more
+        for entry in &mut backup {
+            assert!(
+                KNOWN_ALGORITHMS.contains(&entry.algorithm.as_str()),
+                "Unknown algorithm '{}' in backup of resource '{}'",
+                entry.algorithm,
+                entry.resource
+            );
+            if let Some(method) = &entry.two_factor {
+                assert!(
+                    TWO_FACTOR_METHODS.contains(&method.as_str()),
+                    "Unknown two-factor method '{}' in backup of resource '{}'",
+                    method,
+                    entry.resource
+                );
+            }
+            self.check_not_trivial(&entry.enc_password, &account_id, &entry.identifier, &entry.resource);
+            assert_custom_fields(&entry.custom_fields);
+            assert!(entry.attachments.len() <= MAX_ATTACHMENTS, "A key can hold at most {} attachments", MAX_ATTACHMENTS);
+            entry.attachments.iter().for_each(|cid| assert_attachment(cid));
+            entry.tags = normalize_tags(&entry.tags);
+            assert_url(&entry.url);
+            entry.security_qa.iter().for_each(|(question, answer)| assert_security_qa(question, answer));
+            assert!(
+                entry.security_qa.len() <= MAX_SECURITY_QUESTIONS,
+                "A key can hold at most {} security questions",
+                MAX_SECURITY_QUESTIONS
+            );
+            entry.backup_codes.iter().for_each(|(code, _)| assert_backup_code(code));
+            assert!(entry.backup_codes.len() <= MAX_BACKUP_CODES, "A key can hold at most {} backup codes", MAX_BACKUP_CODES);
+            assert_notes(&entry.notes);
+            if let Some(clear_secs) = entry.clipboard_clear_secs {
+                assert_clipboard_clear(clear_secs);
+            }
+            if let Some(reminder) = &entry.reminder {
+                assert_reminder(reminder);
+            }
+        }
+
+        for entry in backup {
+            let KeyBackup {
+                resource, identifier, enc_password, algorithm, custom_fields, attachments, tags, url, security_qa,
+                backup_codes, notes, archived, clipboard_clear_secs, reminder,
+                two_factor,
+            } = entry;
+            self.store_client_value(account_id.clone(), resource.clone(), identifier, enc_password, false);
+            self.update_key(&account_id, &resource, |key| {
+                key.algorithm = algorithm;
+                key.custom_fields = custom_fields;
+                key.attachments = attachments;
+                key.tags = tags;
+                key.url = url;
+                key.security_qa = security_qa;
+                key.backup_codes = backup_codes;
+                key.notes = notes;
+                key.archived = archived;
+                key.clipboard_clear_secs = clipboard_clear_secs;
+                key.reminder = reminder;
+                key.two_factor = two_factor;
+            });
+        }
+    }
+
+    // Stable 32-bit value a frontend can map to a color or icon for the resource. Resource names
+    // are case-insensitive, so casing doesn't change it.
+    pub fn resource_theme_seed(resource: String) -> u32 {
+        let hash = env::sha256(resource.to_lowercase().as_bytes());
+        u32::from_le_bytes(hash[..4].try_into().unwrap())
+    }
+
+    // Stateless mode: derives the same password from the same master secret and resource every
+    // time, so nothing has to be stored. `charset_flags` combines the CHARSET_* bits.
+    pub fn derive_password(master: String, resource: String, length: u32, charset_flags: u8) -> String {
+        let charset = validate_generation_params(length, &classes_from_flags(charset_flags));
+
+        let mut seed = [master.as_bytes(), &[0], resource.as_bytes()].concat();
+        for _round in 0..DERIVATION_ROUNDS {
+            seed = env::sha256(&seed);
+        }
+
+        // Rejection sampling keeps every character equally likely
+        let limit = 256 - 256 % charset.len();
+        let mut password = String::new();
+        let mut counter: u32 = 0;
+        while password.len() < length as usize {
+            let block = env::sha256(&[seed.as_slice(), &counter.to_le_bytes()].concat());
+            for byte in block {
+                if (byte as usize) < limit && password.len() < length as usize {
+                    password.push(charset[byte as usize % charset.len()]);
+                }
+            }
+            counter += 1;
+        }
+        password
+    }
+
+    // Checks that exported entries still match the hash they were exported with
+    pub fn verify_snapshot(entries: Vec<(String, String, String)>, hash: String) -> bool {
+        snapshot_hash(&entries) == hash
+    }
+
+    // Dry run for an import of (resource, identifier, enc_password) entries into the signer's keys:
+    // the resources that would overwrite a stored key or appear more than once in the input,
+    // ignoring case, sorted
+    pub fn detect_import_conflicts(&self, entries: Vec<(String, String, String)>) -> Vec<String> {
+        let account_id = env::signer_account_id();
+        let mut conflicts: Vec<String> = vec![];
+        for (i, (resource, _, _)) in entries.iter().enumerate() {
+            let normalized = resource.to_lowercase();
+            let repeated = entries[..i].iter().any(|(other, _, _)| other.to_lowercase() == normalized);
+            let reported = conflicts.iter().any(|conflict| conflict.to_lowercase() == normalized);
+            if !reported && (repeated || self.key(&account_id, resource).is_some()) {
+                conflicts.push(resource.clone());
+            }
+        }
+        conflicts.sort();
+        conflicts
+    }
+
+    pub fn add_attachment(&mut self, resource: String, cid: String) {
+        self.touch();
+        assert_attachment(&cid);
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| {
+            if !key.attachments.contains(&cid) {
+                assert!(key.attachments.len() < MAX_ATTACHMENTS, "A key can hold at most {} attachments", MAX_ATTACHMENTS);
+                key.attachments.push(cid);
+            }
+        });
+    }
+
+    pub fn remove_attachment(&mut self, resource: String, cid: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| {
+            match key.attachments.iter().position(|attachment| *attachment == cid) {
+                Some(position) => {
+                    key.attachments.remove(position);
+                }
+                None => panic!("Attachment '{}' not found on resource '{}'", cid, resource),
+            }
+        });
+    }
+
+    pub fn list_attachments(&self, account_id: String, resource: String) -> Vec<String> {
+        match self.key(&account_id, &resource) {
+            Some(key) => key.attachments.clone(),
+            None => vec![],
+        }
+    }
+
+    // Opts the signer in or out of the strength guard: with it, no value the client supplies (set or
+    // import) replaces a stronger one unless forced through set_password_with_force. Generated
+    // values, including rotations, are never held back.
+    pub fn set_strength_guard(&mut self, enabled: bool) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.strength_guarded.retain(|guarded| *guarded != account_id);
+        if enabled {
+            self.strength_guarded.push(account_id);
+        }
+    }
+
+    pub fn is_strength_guarded(&self, account_id: String) -> bool {
+        self.strength_guarded.contains(&account_id)
+    }
+
+    // With an auto-lock, the signer can only read key material while unlocked: once inactivity_ns
+    // passes without an unlock call or any other transaction from it, a session left open on a
+    // shared device stops revealing anything. 0 turns it off. Setting it locks the account until
+    // the next unlock.
+    pub fn set_auto_lock(&mut self, inactivity_ns: u64) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.active_at.remove(&account_id);
+        if inactivity_ns == 0 {
+            self.auto_lock_ns.remove(&account_id);
+        } else {
+            self.auto_lock_ns.insert(account_id, inactivity_ns);
+        }
+    }
+
+    pub fn unlock(&mut self) {
+        self.touch();
+        self.active_at.insert(env::signer_account_id(), env::block_timestamp());
+    }
+
+    pub fn is_locked(&self, account_id: String) -> bool {
+        match self.auto_lock_ns.get(&account_id) {
+            Some(inactivity_ns) => self.active_at.get(&account_id)
+                .is_none_or(|active_at| env::block_timestamp() >= active_at.saturating_add(*inactivity_ns)),
+            None => false,
+        }
+    }
+
+    // Identifier (usually an email) given to the signer's keys when they are stored or generated
+    // with an empty one; an empty value clears it
+    pub fn set_default_identifier(&mut self, identifier: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        assert_key_names("", &identifier);
+        if identifier.is_empty() {
+            self.default_identifiers.remove(&account_id);
+        } else {
+            self.default_identifiers.insert(account_id, identifier);
+        }
+    }
+
+    pub fn get_default_identifier(&self, account_id: String) -> String {
+        self.default_identifiers.get(&account_id).cloned().unwrap_or_default()
+    }
+
+    // Per-key clipboard clear timeout for frontends; None falls back to the frontend's default
+    pub fn set_clipboard_clear(&mut self, resource: String, clear_secs: Option<u32>) {
+        self.touch();
+        if let Some(clear_secs) = clear_secs {
+            assert_clipboard_clear(clear_secs);
+        }
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.clipboard_clear_secs = clear_secs);
+    }
+
+    pub fn get_clipboard_clear(&self, account_id: String, resource: String) -> Option<u32> {
+        self.key(&account_id, &resource).and_then(|key| key.clipboard_clear_secs)
+    }
+
+    // Second factor the service has set up for the key's account; None when it has none
+    pub fn set_two_factor(&mut self, resource: String, method: Option<String>) {
+        self.touch();
+        if let Some(method) = &method {
+            assert!(
+                TWO_FACTOR_METHODS.contains(&method.as_str()),
+                "Unknown two-factor method '{}', expected one of {}",
+                method,
+                TWO_FACTOR_METHODS.join(", ")
+            );
+        }
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.two_factor = method);
+    }
+
+    pub fn get_two_factor(&self, account_id: String, resource: String) -> Option<String> {
+        self.key(&account_id, &resource).and_then(|key| key.two_factor.clone())
+    }
+
+    // Hint frontends show whenever the key is pulled up; None removes it
+    pub fn set_reminder(&mut self, resource: String, reminder: Option<String>) {
+        self.touch();
+        if let Some(reminder) = &reminder {
+            assert_reminder(reminder);
+        }
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.reminder = reminder);
+    }
+
+    pub fn get_reminder(&self, account_id: String, resource: String) -> Option<String> {
+        self.key(&account_id, &resource).and_then(|key| key.reminder.clone())
+    }
+
+    // Records the site one of the signer's keys belongs to; an empty url clears it
+    pub fn set_url(&mut self, resource: String, url: String) {
+        self.touch();
+        assert_url(&url);
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.url = url);
+    }
+
+    pub fn get_url(&self, account_id: String, resource: String) -> String {
+        match self.key(&account_id, &resource) {
+            Some(key) => key.url.clone(),
+            None => "".to_string(),
+        }
+    }
+
+    pub fn set_notes(&mut self, resource: String, notes: String) {
+        self.touch();
+        assert_notes(&notes);
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.notes = notes);
+    }
+
+    // Readable by the same accounts as the password itself
+    pub fn get_notes(&self, account_id: String, resource: String) -> String {
+        self.assert_can_read(&account_id, &resource);
+        match self.key(&account_id, &resource) {
+            Some(key) => key.notes.clone(),
+            None => "".to_string(),
+        }
+    }
+
+    // Stores the rows of a KeePass CSV export (Title, Username, Password, URL, Notes) as keys of the
+    // signer, replacing keys with the same title. A leading header row is skipped.
+    pub fn import_keepass_csv(&mut self, rows: Vec<String>) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        for (index, row) in rows.iter().enumerate() {
+            let fields = parse_csv_row(row, index + 1);
+            let is_header = fields.iter().zip(KEEPASS_COLUMNS.iter()).all(|(field, column)| field.eq_ignore_ascii_case(column));
+            if index == 0 && fields.len() == KEEPASS_COLUMNS.len() && is_header {
+                continue;
+            }
+            assert!(
+                fields.len() == KEEPASS_COLUMNS.len(),
+                "Row {} has {} columns, expected {}",
+                index + 1,
+                fields.len(),
+                KEEPASS_COLUMNS.len()
+            );
+
+            let mut fields = fields.into_iter();
+            let mut next = || fields.next().unwrap();
+            let (resource, identifier, password, url, notes) = (next(), next(), next(), next(), next());
+            assert_url(&url);
+            assert_notes(&notes);
+            self.check_not_trivial(&password, &account_id, &identifier, &resource);
+            self.store_client_value(account_id.clone(), resource.clone(), identifier, password, false);
+            self.update_key(&account_id, &resource, |key| {
+                key.url = url;
+                key.notes = notes;
+            });
+        }
+    }
+
+    // Lists the account's resources missing an identifier or a url, so they can be completed
+    pub fn incomplete_entries(&self, account_id: String) -> Vec<String> {
+        let mut incomplete: Vec<String> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| key.identifier.is_empty() || key.url.is_empty())
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        incomplete.sort();
+        incomplete
+    }
+
+    // Replaces the tags of one of the signer's keys
+    pub fn set_tags(&mut self, resource: String, tags: Vec<String>) {
+        self.touch();
+        let normalized = normalize_tags(&tags);
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| key.tags = normalized);
+    }
+
+    // Adds a tag to each listed key of the signer, skipping resources it has no key for. Returns the
+    // number of keys that carry the tag afterwards.
+    pub fn tag_many(&mut self, resources: Vec<String>, tag: String) -> u64 {
+        self.touch();
+        let tag = normalize_tag(&tag);
+        let account_id = env::signer_account_id();
+        let mut tagged = 0;
+        for resource in resources {
+            if self.key(&account_id, &resource).is_none() {
+                continue;
+            }
+            self.update_key(&account_id, &resource, |key| {
+                if !key.tags.contains(&tag) {
+                    assert!(key.tags.len() < MAX_TAGS, "A key can hold at most {} tags", MAX_TAGS);
+                    key.tags.push(tag.clone());
+                }
+            });
+            tagged += 1;
+        }
+        tagged
+    }
+
+    // Replaces `old_tag` with `new_tag` on every key of the signer carrying it, dropping it instead
+    // where `new_tag` is already present. Returns the number of keys changed.
+    pub fn rename_tag(&mut self, old_tag: String, new_tag: String) -> u64 {
+        self.touch();
+        let (old_tag, new_tag) = (normalize_tag(&old_tag), normalize_tag(&new_tag));
+        let account_id = env::signer_account_id();
+        self.assert_allowed(&account_id);
+        let mut renamed = 0;
+        if let Some(record) = self.keys.get_mut(&account_id) {
+            for key in record.values_mut() {
+                let position = match key.tags.iter().position(|tag| *tag == old_tag) {
+                    Some(position) => position,
+                    None => continue,
+                };
+                if key.tags.contains(&new_tag) {
+                    key.tags.remove(position);
+                } else {
+                    key.tags[position] = new_tag.clone();
+                }
+                renamed += 1;
+            }
+        }
+        if renamed > 0 {
+            self.update_checksum(&account_id);
+        }
+        renamed
+    }
+
+    pub fn get_tags(&self, account_id: String, resource: String) -> Vec<String> {
+        match self.key(&account_id, &resource) {
+            Some(key) => key.tags.clone(),
+            None => vec![],
+        }
+    }
+
+    // Lists the account's resources carrying every one of `tags` when `match_all` is set, or at least
+    // one of them otherwise, sorted
+    pub fn list_by_tags(&self, account_id: String, tags: Vec<String>, match_all: bool) -> Vec<String> {
+        let tags: Vec<String> = tags.iter().map(|tag| normalize_tag(tag)).collect();
+        let matches = |key: &Key| {
+            if match_all {
+                tags.iter().all(|tag| key.tags.contains(tag))
+            } else {
+                tags.iter().any(|tag| key.tags.contains(tag))
+            }
+        };
+        let mut resources: Vec<String> = match self.keys.get(&account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| matches(key))
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        resources.sort();
+        resources
+    }
+
+    // Number of the account's keys carrying each tag, most used first and then by name. Keys without
+    // tags are counted under "untagged".
+    pub fn tag_counts(&self, account_id: String) -> Vec<(String, u64)> {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for key in self.keys.get(&account_id).into_iter().flat_map(|record| record.values()) {
+            if key.tags.is_empty() {
+                *counts.entry(UNTAGGED.to_string()).or_default() += 1;
+            }
+            for tag in &key.tags {
+                *counts.entry(tag.clone()).or_default() += 1;
+            }
+        }
+        let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+        counts.sort_by(|(a_tag, a_count), (b_tag, b_count)| b_count.cmp(a_count).then_with(|| a_tag.cmp(b_tag)));
+        counts
+    }
+
+    // Records the answer given to a security question of one of the signer's keys, replacing the
+    // answer if the question is already there
+    pub fn set_security_question(&mut self, resource: String, question: String, answer: String) {
+        self.touch();
+        assert_security_qa(&question, &answer);
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| {
+            match key.security_qa.iter_mut().find(|(existing, _)| *existing == question) {
+                Some(entry) => entry.1 = answer,
+                None => {
+                    assert!(
+                        key.security_qa.len() < MAX_SECURITY_QUESTIONS,
+                        "A key can hold at most {} security questions",
+                        MAX_SECURITY_QUESTIONS
+                    );
+                    key.security_qa.push((question, answer));
+                }
+            }
+        });
+    }
+
+    // Readable by the same accounts as the password itself
+    pub fn get_security_questions(&self, account_id: String, resource: String) -> Vec<(String, String)> {
+        self.assert_can_read(&account_id, &resource);
+        match self.key(&account_id, &resource) {
+            Some(key) => key.security_qa.clone(),
+            None => vec![],
+        }
+    }
+
+    // Adds the backup codes a service issued for one of the signer's keys; codes the key already
+    // holds are skipped
+    pub fn add_backup_codes(&mut self, resource: String, codes: Vec<String>) {
+        self.touch();
+        codes.iter().for_each(|code| assert_backup_code(code));
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| {
+            for code in codes {
+                if !key.backup_codes.iter().any(|(existing, _)| *existing == code) {
+                    key.backup_codes.push((code, false));
+                }
+            }
+            assert!(
+                key.backup_codes.len() <= MAX_BACKUP_CODES,
+                "A key can hold at most {} backup codes",
+                MAX_BACKUP_CODES
+            );
+        });
+    }
+
+    pub fn mark_code_used(&mut self, resource: String, code: String) {
+        self.touch();
+        let account_id = env::signer_account_id();
+        self.update_key(&account_id, &resource, |key| {
+            match key.backup_codes.iter_mut().find(|(existing, _)| *existing == code) {
+                Some(entry) => entry.1 = true,
+                None => panic!("Resource '{}' has no such backup code", resource),
+            }
+        });
+    }
+
+    // Codes not marked used yet, in the order they were added. Readable by the same accounts as
+    // the password itself.
+    pub fn unused_backup_codes(&self, account_id: String, resource: String) -> Vec<String> {
+        self.assert_can_read(&account_id, &resource);
+        match self.key(&account_id, &resource) {
+            Some(key) => key.backup_codes.iter()
+                .filter(|(_, used)| !used)
+                .map(|(code, _)| code.clone())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Fields may hold secrets like PINs, so they are readable by the same accounts as the password
+    pub fn get_custom_fields(&self, account_id: String, resource: String) -> Vec<(String, String)> {
+        self.assert_can_read(&account_id, &resource);
+        match self.key(&account_id, &resource) {
+            Some(key) => key.custom_fields.clone(),
+            None => vec![],
+        }
+    }
+}
+
+// Which of (lower, upper, digit, special) characters the password contains. Anything that isn't a
+// letter or a digit counts towards the special characters.
+fn classes_present(password: &str) -> (bool, bool, bool, bool) {
+    let has = |set: &str| password.chars().any(|c| set.contains(c));
+    (
+        has(LOWER_CASE_LETTERS),
+        has(UPPER_CASE_LETTERS),
+        has(NUMBERS),
+        password.chars().any(|c| !c.is_ascii_alphanumeric()),
+    )
+}
+
+// Size of the character pool a password draws from, judged by the character classes it contains
+fn charset_size(password: &str) -> u32 {
+    let (lower, upper, digit, special) = classes_present(password);
+    let mut size = 0;
+    if lower {
+        size += LOWER_CASE_LETTERS.len() as u32;
+    }
+    if upper {
+        size += UPPER_CASE_LETTERS.len() as u32;
+    }
+    if digit {
+        size += NUMBERS.len() as u32;
+    }
+    if special {
+        size += SPECIAL_CHARS.len() as u32;
+    }
+    size
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Serializes the keys sorted by resource so the checksum doesn't depend on map order
+fn record_checksum(record: &HashMap<String, Key>) -> Vec<u8> {
+    let mut resources: Vec<&String> = record.keys().collect();
+    resources.sort();
+    let mut data = vec![];
+    for resource in resources {
+        BorshSerialize::serialize(resource, &mut data).unwrap();
+        BorshSerialize::serialize(&record[resource], &mut data).unwrap();
+    }
+    env::sha256(&data)
+}
+
+fn snapshot_hash(entries: &[(String, String, String)]) -> String {
+    to_hex(&env::sha256(&entries.try_to_vec().unwrap()))
+}
+
+fn event_message(event: &str, data: serde_json::Value) -> String {
+    json!({ "event": event, "data": data }).to_string()
+}
+
+fn log_event(event: &str, data: serde_json::Value) {
+    log(&event_message(event, data));
+}
+
+// Every log of the contract goes through here. The mocked blockchain keeps its logs to itself, so
+// test builds also record them for the tests to read back.
+fn log(message: &str) {
+    env::log(message.as_bytes());
+    #[cfg(test)]
+    TEST_LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+}
+
+#[cfg(test)]
+thread_local! {
+    static TEST_LOGS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+fn shares_revoked_event(owner: &str, resource: &str, grantees: &[String]) -> String {
+    event_message("shares_revoked", json!({ "owner": owner, "resource": resource, "grantees": grantees }))
+}
+
+fn break_glass_event(caller: &str, owner: &str, resource: &str, timestamp: u64) -> String {
+    event_message(
+        "break_glass_access",
+        json!({ "caller": caller, "owner": owner, "resource": resource, "timestamp": timestamp }),
+    )
+}
+
+// Names what a trivially guessable password is equal to, if anything (case-insensitive)
+fn trivial_match(password: &str, account_id: &str, identifier: &str, resource: &str) -> Option<&'static str> {
+    let password = password.to_lowercase();
+    if password == account_id.to_lowercase() {
+        Some("account id")
+    } else if password == identifier.to_lowercase() {
+        Some("identifier")
+    } else if password == resource.to_lowercase() {
+        Some("resource name")
+    } else {
+        None
+    }
+}
+
+fn weak_pattern(password: &str) -> bool {
+    let chars: Vec<char> = password.to_lowercase().chars().collect();
+    chars.windows(WEAK_PATTERN_RUN).any(|window| {
+        let stretch: String = window.iter().collect();
+        let reversed: String = window.iter().rev().collect();
+        let on_row = KEYBOARD_ROWS.iter().any(|row| row.contains(&stretch) || row.contains(&reversed));
+        let step = |delta: i64| window.windows(2).all(|pair| pair[1] as i64 - pair[0] as i64 == delta);
+        on_row || step(1) || step(-1)
+    })
+}
+
+// Birthdays and phone numbers: a run of exactly 8 digits (ddmmyyyy, yyyymmdd), a run of 10 or
+// more digits, or a dd/mm/yyyy date with '/', '-' or '.' as separators
+fn personal_pattern(password: &str) -> bool {
+    let chars: Vec<char> = password.chars().collect();
+    let digit_run = chars.split(|c| !c.is_ascii_digit()).any(|run| run.len() == 8 || run.len() >= 10);
+    let date = chars.windows(10).any(|window| {
+        let separator = window[2];
+        "/-.".contains(separator)
+            && window[5] == separator
+            && [0, 1, 3, 4, 6, 7, 8, 9].iter().all(|i| window[*i].is_ascii_digit())
+    });
+    digit_run || date
+}
+
+fn contains_username(password: &str, identifier: &str) -> bool {
+    let username = identifier.split('@').next().unwrap_or_default().to_lowercase();
+    !username.is_empty() && password.to_lowercase().contains(&username)
+}
+
+// Logs are public and permanent, so the event doesn't say what the value matched
+fn warn_if_trivial(password: &str, account_id: &str, identifier: &str, resource: &str) {
+    if trivial_match(password, account_id, identifier, resource).is_some() {
+        log_event("trivial_password", json!({ "account_id": account_id, "resource": resource }));
+    }
+}
+
+fn seeded_rng() -> StdRng {
+    let seed = env::random_seed();
+    if seed.len() < 32 || seed.iter().all(|byte| *byte == 0) {
+        env::panic(b"Random seed is unavailable in this context, passwords can only be generated in a transaction");
+    }
+    SeedableRng::from_seed(seed[..32].try_into().unwrap())
+}
+
+// Insecure by design: the seed only depends on the counter, see EntropyMode
+fn counter_rng(counter: u64) -> StdRng {
+    let seed = env::sha256(&[b"keychain-counter".as_ref(), &counter.to_le_bytes()].concat());
+    SeedableRng::from_seed(seed[..32].try_into().unwrap())
+}
+
+fn salted_fingerprint(salt: &str, key_fingerprint: &str) -> String {
+    to_hex(&env::sha256(&[salt.as_bytes(), key_fingerprint.as_bytes()].concat()))
+}
+
+fn ephemeral_rng(entropy: &str) -> StdRng {
+    let seed = env::sha256(&[b"keychain-ephemeral".as_ref(), &env::random_seed(), entropy.as_bytes()].concat());
+    SeedableRng::from_seed(seed[..32].try_into().unwrap())
+}
+
+// Draws `length` characters from the given character classes, guaranteeing at least `min`
+// characters from each `(class, min)` pair. The guaranteed characters are shuffled into random
+// positions.
+fn generate_password(rng: &mut StdRng, length: u32, classes: &[(&str, u32)]) -> String {
+    let selected_set: Vec<char> = classes.iter().map(|(class, _)| *class).collect::<String>().chars().collect();
+
+    let mut chars: Vec<char> = Vec::with_capacity(length as usize);
+    for (class, min) in classes {
+        let class: Vec<char> = class.chars().collect();
+        for _n in 0..*min {
+            chars.push(class[rng.gen_range(0, class.len())]);
+        }
+    }
+    while chars.len() < length as usize {
+        chars.push(selected_set[rng.gen_range(0, selected_set.len())]);
+    }
+    chars.shuffle(rng);
+
+    let password: String = chars.into_iter().collect();
+    assert_exact_length(&password, length);
+    password
+}
+
+// Retries generation until no character repeats back to back. Tight constraints (e.g. a long
+// all-digit password) could make this loop for a long time and burn gas, so after `max_attempts`
+// candidates the last one is repaired instead: every repeat is deterministically replaced by the
+// first character of the same class that differs from both neighbours. Keeping the class means
+// the minimums still hold, so generation always terminates with a compliant password. Named apart
+// from the generate_without_repeats method, whose wasm export would clash with a free fn of the
+// same name.
+fn generate_password_without_repeats(rng: &mut StdRng, length: u32, classes: &[(&str, u32)], max_attempts: u32) -> String {
+    for _attempt in 0..max_attempts {
+        let candidate = generate_password(rng, length, classes);
+        if !has_consecutive_repeats(&candidate) {
+            return candidate;
+        }
+    }
+
+    let mut chars: Vec<char> = generate_password(rng, length, classes).chars().collect();
+    for i in 1..chars.len() {
+        if chars[i] == chars[i - 1] {
+            let next = chars.get(i + 1).copied();
+            let (class, _) = classes.iter().find(|(class, _)| class.contains(chars[i])).unwrap();
+            chars[i] = class.chars()
+                .find(|c| *c != chars[i - 1] && Some(*c) != next)
+                .unwrap();
+        }
+    }
+
+    let password: String = chars.into_iter().collect();
+    assert_exact_length(&password, length);
+    password
+}
+
+// Retries generation until the candidate is none of the `used` values. A fresh rng starts from the
+// same block seed, so without this two regenerations in one block would produce the same value.
+// After `max_attempts` candidates the last one is kept even if it was used before.
+fn generate_distinct_password(rng: &mut StdRng, length: u32, classes: &[(&str, u32)], used: &[&str], max_attempts: u32) -> String {
+    let mut candidate = generate_password(rng, length, classes);
+    for _attempt in 1..max_attempts {
+        if !used.contains(&candidate.as_str()) {
+            break;
+        }
+        candidate = generate_password(rng, length, classes);
+    }
+    candidate
+}
+
+fn has_consecutive_repeats(password: &str) -> bool {
+    password.chars().zip(password.chars().skip(1)).any(|(a, b)| a == b)
+}
+
+// Length of the longest stretch of one character repeated back to back
+fn longest_repeat(password: &str) -> u32 {
+    let chars: Vec<char> = password.chars().collect();
+    let runs = chars.chunk_by(|a, b| a == b);
+    runs.map(|run| run.len() as u32).max().unwrap_or(0)
+}
+
+fn classes_from_flags(flags: u8) -> Vec<(&'static str, u32)> {
+    [
+        (CHARSET_LOWER, LOWER_CASE_LETTERS),
+        (CHARSET_UPPER, UPPER_CASE_LETTERS),
+        (CHARSET_DIGITS, NUMBERS),
+        (CHARSET_SPECIAL, SPECIAL_CHARS),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .map(|(_, class)| (*class, 0))
+    .collect()
+}
+
+// Shared checks of every password generator: the length must be within bounds, there must be
+// something to draw from and the class minimums must fit. Returns the pool of distinct characters
+// of all classes, in order of first appearance.
+fn validate_generation_params(length: u32, classes: &[(&str, u32)]) -> Vec<char> {
+    assert!(
+        (MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length),
+        "Password length must be between {} and {}",
+        MIN_PASSWORD_LENGTH,
+        MAX_PASSWORD_LENGTH
+    );
+    let required: u64 = classes.iter().map(|(_, min)| *min as u64).sum();
+    assert!(required <= length as u64, "Minimum class counts add up to more than the password length");
+
+    let mut pool: Vec<char> = vec![];
+    for c in classes.iter().flat_map(|(class, _)| class.chars()) {
+        if !pool.contains(&c) {
+            pool.push(c);
+        }
+    }
+    assert!(!pool.is_empty(), "At least one character set must be selected");
+    pool
+}
+
+// Final guard for every generator: whatever retries or substitutions happened, the result must
+// be exactly as long as requested so it never exceeds a site's maximum. No character set holds
+// whitespace, which would silently break form submissions; debug builds check that it stays so.
+fn assert_exact_length(password: &str, length: u32) {
+    debug_assert!(
+        !password.chars().any(char::is_whitespace),
+        "Generated password contains whitespace"
+    );
+    let actual = password.chars().count();
+    assert!(
+        actual == length as usize,
+        "Generated password has {} characters instead of the requested {}",
+        actual,
+        length
+    );
+}
+
+fn assert_credential_cap(count: usize, max_credentials: u64) {
+    assert!(count as u64 <= max_credentials, "An account can store at most {} credentials", max_credentials);
+}
+
+// Every write goes through this check so a caller cannot inflate storage with huge names
+fn assert_key_names(resource: &str, identifier: &str) {
+    assert!(
+        resource.len() <= MAX_RESOURCE_BYTES,
+        "Resource name is {} bytes long, the limit is {}",
+        resource.len(),
+        MAX_RESOURCE_BYTES
+    );
+    assert!(
+        identifier.len() <= MAX_IDENTIFIER_BYTES,
+        "Identifier is {} bytes long, the limit is {}",
+        identifier.len(),
+        MAX_IDENTIFIER_BYTES
+    );
+}
+
+// Id of the record holding the keys of one of the account's vaults
+fn vault_record_id(account_id: &str, vault: &str) -> String {
+    if vault.is_empty() || vault == DEFAULT_VAULT {
+        return account_id.to_string();
+    }
+    assert!(vault.len() <= MAX_VAULT_BYTES, "Vault name is {} bytes long, the limit is {}", vault.len(), MAX_VAULT_BYTES);
+    format!("{}{}{}", account_id, VAULT_SEPARATOR, vault)
+}
+
+// Host part of a url, e.g. "accounts.example.com" for "https://accounts.example.com:443/login"
+fn url_domain(url: &str) -> String {
+    let rest = url.split("://").nth(1).unwrap_or(url);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.rsplit('@').next().unwrap_or_default();
+    host.split(':').next().unwrap_or_default().to_lowercase()
+}
+
+fn team_record_id(team_id: &str) -> String {
+    format!("{}{}", TEAM_PREFIX, team_id)
+}
+
+// Account owning a record, whichever of its vaults the record holds
+fn record_owner(record_id: &str) -> &str {
+    record_id.split(VAULT_SEPARATOR).next().unwrap_or_default()
+}
+
+// Resource names are matched case-insensitively, so "GitHub" and "github" are the same key. Finds
+// the name an entry for `resource` is stored under, which keeps the casing it was first stored with.
+fn stored_resource<V>(entries: &HashMap<String, V>, resource: &str) -> Option<String> {
+    if entries.contains_key(resource) {
+        return Some(resource.to_string());
+    }
+    let normalized = resource.to_lowercase();
+    entries.keys().find(|stored| stored.to_lowercase() == normalized).cloned()
+}
+
+fn assert_url(url: &str) {
+    assert!(
+        url.len() <= MAX_URL_BYTES && !url.chars().any(char::is_whitespace),
+        "URL must be at most {} bytes without whitespace",
+        MAX_URL_BYTES
+    );
+}
+
+fn assert_notes(notes: &str) {
+    assert!(notes.len() <= MAX_NOTES_BYTES, "Notes are {} bytes long, the limit is {}", notes.len(), MAX_NOTES_BYTES);
+}
+
+fn assert_reminder(reminder: &str) {
+    assert!(
+        reminder.len() <= MAX_REMINDER_BYTES,
+        "Reminder is {} bytes long, the limit is {}",
+        reminder.len(),
+        MAX_REMINDER_BYTES
+    );
+}
+
+fn assert_clipboard_clear(clear_secs: u32) {
+    assert!(
+        (MIN_CLIPBOARD_CLEAR_SECS..=MAX_CLIPBOARD_CLEAR_SECS).contains(&clear_secs),
+        "Clipboard clear timeout must be between {} and {} seconds",
+        MIN_CLIPBOARD_CLEAR_SECS,
+        MAX_CLIPBOARD_CLEAR_SECS
+    );
+}
+
+fn assert_custom_fields(fields: &[(String, String)]) {
+    assert!(fields.iter().all(|(name, _)| !name.is_empty()), "Custom field name must not be empty");
+    assert!(fields.len() <= MAX_CUSTOM_FIELDS, "A key can hold at most {} custom fields", MAX_CUSTOM_FIELDS);
+    let total_bytes: usize = fields.iter().map(|(name, value)| name.len() + value.len()).sum();
+    assert!(
+        total_bytes <= MAX_CUSTOM_FIELDS_BYTES,
+        "Custom fields of a key can take at most {} bytes",
+        MAX_CUSTOM_FIELDS_BYTES
+    );
+}
+
+fn assert_security_qa(question: &str, answer: &str) {
+    assert!(!question.is_empty(), "Security question must not be empty");
+    assert!(
+        question.len() <= MAX_SECURITY_QA_BYTES && answer.len() <= MAX_SECURITY_QA_BYTES,
+        "Security questions and answers must be at most {} bytes",
+        MAX_SECURITY_QA_BYTES
+    );
+}
+
+fn assert_backup_code(code: &str) {
+    assert!(
+        !code.is_empty() && code.len() <= MAX_BACKUP_CODE_BYTES,
+        "Backup codes must be non-empty and at most {} bytes",
+        MAX_BACKUP_CODE_BYTES
+    );
+}
+
+fn assert_attachment(cid: &str) {
+    assert!(
+        !cid.is_empty() && cid.len() <= MAX_CID_BYTES && !cid.chars().any(char::is_whitespace),
+        "Attachment must be a content identifier of at most {} bytes",
+        MAX_CID_BYTES
+    );
+}
+
+// Splits one CSV line into fields. Fields may be wrapped in double quotes to hold commas, with a
+// doubled quote ("") standing for a literal one.
+fn parse_csv_row(row: &str, row_number: usize) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    assert!(!quoted, "Row {} has an unterminated quoted field", row_number);
+    fields.push(field);
+    fields
+}
+
+// Trims and lowercases a tag so "Work" and " work" end up as the same label
+fn normalize_tag(tag: &str) -> String {
+    let tag = tag.trim().to_lowercase();
+    assert!(
+        !tag.is_empty() && tag.len() <= MAX_TAG_BYTES,
+        "Tags must be non-empty and at most {} bytes",
+        MAX_TAG_BYTES
+    );
+    tag
+}
+
+// Normalized tags without duplicates, in the order given
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = vec![];
+    for tag in tags {
+        let tag = normalize_tag(tag);
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    assert!(normalized.len() <= MAX_TAGS, "A key can hold at most {} tags", MAX_TAGS);
+    normalized
+}
+
+fn assert_resource_hash(resource_hash: &str) {
+    assert!(is_sha256_hex(resource_hash), "Resource hash must be a lowercase hex-encoded sha256 digest");
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+// Estimated entropy scaled to 0-100, reaching 100 at FULL_STRENGTH_BITS. Keyboard stretches and
+// runs make a password far easier to guess than its length suggests, so they halve the score.
+fn strength_score(password: &str) -> u8 {
+    let score = (estimate_entropy_bits(password) / FULL_STRENGTH_BITS * 100.0).min(100.0) as u8;
+    if weak_pattern(password) {
+        score / 2
+    } else {
+        score
+    }
+}
+
+fn estimate_entropy_bits(password: &str) -> f64 {
+    let size = charset_size(password);
+    if size == 0 {
+        return 0.0;
+    }
+    password.chars().count() as f64 * (size as f64).log2()
+}
+
+impl Keychain {
+    fn key(&self, account_id: &str, resource: &str) -> Option<&Key> {
+        let record = self.keys.get(account_id)?;
+        record.get(&stored_resource(record, resource)?)
+    }
+
+    // Rules of the url's domain, or of the closest parent domain that has some
+    fn rules_for_url(&self, url: &str) -> Option<&SiteRules> {
+        let host = url_domain(url);
+        let mut domain = host.as_str();
+        while !domain.is_empty() {
+            if let Some(rules) = self.site_rules.get(domain) {
+                return Some(rules);
+            }
+            domain = &domain[domain.find('.')? + 1..];
+        }
+        None
+    }
+
+    // Audits across an account's keys describe all of its values, so only the account itself may
+    // run them, and only in a transaction
+    fn assert_own_account(&self, account_id: &str) {
+        let owner = record_owner(account_id);
+        assert_eq!(env::signer_account_id(), owner, "Only '{}' can audit its keys", owner);
+        self.assert_unlocked(owner);
+    }
+
+    fn assert_team_member(&self, team_id: &str, account_id: &str) {
+        let is_member = self.teams.get(team_id).is_some_and(|team| team.members.iter().any(|member| member == account_id));
+        assert!(is_member, "Account '{}' is not a member of team '{}'", account_id, team_id);
+    }
+
+    fn team_mut_as_owner(&mut self, team_id: &str) -> &mut Team {
+        let account_id = env::signer_account_id();
+        match self.teams.get_mut(team_id) {
+            Some(team) if team.owner == account_id => team,
+            Some(_) => panic!("Only the owner of team '{}' can change its members", team_id),
+            None => panic!("Team '{}' does not exist", team_id),
+        }
+    }
+
+    // Number of keys the account holds across all of its vaults
+    fn credential_count(&self, account_id: &str) -> usize {
+        self.keys.iter()
+            .filter(|(record_id, _)| record_owner(record_id) == account_id)
+            .map(|(_, record)| record.len())
+            .sum()
+    }
+
+    // The name an existing key of the account is stored under, or `resource` itself for a new one
+    fn resource_name(&self, account_id: &str, resource: String) -> String {
+        match self.keys.get(account_id).and_then(|record| stored_resource(record, &resource)) {
+            Some(stored) => stored,
+            None => resource,
+        }
+    }
+
+    fn resources_where(&self, account_id: &str, include: impl Fn(&Key) -> bool) -> Vec<String> {
+        let mut resources: Vec<String> = match self.keys.get(account_id) {
+            Some(record) => record.iter()
+                .filter(|(_, key)| include(key))
+                .map(|(resource, _)| resource.clone())
+                .collect(),
+            None => vec![],
+        };
+        resources.sort();
+        resources
+    }
+
+    fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only the owner can call this method");
+    }
+
+    // Values are readable by the account that stored them and by grantees of an active share. A
+    // missing key has nothing to protect, so anyone reading it just gets nothing back; this is
+    // also what a grantee sees once the owner deleted a shared key.
+    fn assert_can_read(&self, account_id: &str, resource: &str) {
+        let reader = env::signer_account_id();
+        let key = match self.key(account_id, resource) {
+            Some(key) => key,
+            None => return,
+        };
+        let granted = reader == record_owner(account_id)
+            || key.shares.iter().any(|share| share.grantee == reader && share.is_active());
+        assert!(granted, "Account '{}' is not allowed to read '{}' of '{}'", reader, resource, account_id);
+        self.assert_unlocked(&reader);
+    }
+
+    // Every path returning key material, or properties of it, goes through here
+    fn assert_unlocked(&self, account_id: &str) {
+        assert!(!self.is_locked(account_id.to_string()), "Account '{}' is locked, call unlock first", account_id);
+    }
+
+    fn assert_allowed(&self, account_id: &str) {
+        // Team keys are written by members, so it is the writing member that must be allowed
+        let account_id = if account_id.starts_with(TEAM_PREFIX) {
+            env::signer_account_id()
+        } else {
+            record_owner(account_id).to_string()
+        };
+        assert!(
+            !self.allowlist_enforced || self.allowed_accounts.contains(&account_id),
+            "Account '{}' is not allowed to store keys",
+            account_id
+        );
+    }
+
+    // Under the strict policy a trivially guessable password is rejected, otherwise it is stored
+    // with a warning event
+    fn check_not_trivial(&self, password: &str, account_id: &str, identifier: &str, resource: &str) {
+        if let Some(matched) = trivial_match(password, account_id, identifier, resource) {
+            assert!(!self.strict_policy, "Password must not be the same as the {}", matched);
+            warn_if_trivial(password, account_id, identifier, resource);
+        }
+    }
+
+    // The weak_password_stored event for a value breaking the policy or following a weak pattern,
+    // if the owner enabled these warnings. Logs are public and permanent, so it only names the key:
+    // neither the value nor what is wrong with it is reported.
+    fn weak_password_warning(&self, password: &str, account_id: &str, resource: &str) -> Option<String> {
+        if !self.warn_weak_passwords || (self.policy.violations(password).is_empty() && !weak_pattern(password)) {
+            return None;
+        }
+        Some(event_message("weak_password_stored", json!({ "account_id": account_id, "resource": resource })))
+    }
+
+    fn index_share(&mut self, grantee: String, owner: &str, resource: &str) {
+        let entries = self.shared_with.entry(grantee).or_default();
+        if !entries.iter().any(|(o, r)| o == owner && r == resource) {
+            entries.push((owner.to_string(), resource.to_string()));
+        }
+    }
+
+    // Drops the reverse index entries of shares that were removed from a key
+    fn unindex_shares(&mut self, owner: &str, resource: &str, shares: &[Share]) {
+        for share in shares {
+            if let Some(entries) = self.shared_with.get_mut(&share.grantee) {
+                entries.retain(|(o, r)| !(o == owner && r == resource));
+                if entries.is_empty() {
+                    self.shared_with.remove(&share.grantee);
+                }
+            }
+        }
+    }
+
+    // Borsh size of a record's keys, its trash and the reverse index entries pointing at it
+    fn record_size(&self, record_id: &str) -> u64 {
+        let keys = self.keys.get(record_id).map_or(0, |record| record.try_to_vec().unwrap().len());
+        let trash = self.trash.get(record_id).map_or(0, |trashed| trashed.try_to_vec().unwrap().len());
+        let index: usize = self.shared_with.values()
+            .flat_map(|entries| entries.iter())
+            .filter(|(owner, _)| owner == record_id)
+            .map(|entry| entry.try_to_vec().unwrap().len())
+            .sum();
+        (keys + trash + index) as u64
+    }
+
+    fn purge_expired_trash(&mut self, account_id: &str) -> u64 {
+        let trashed = match self.trash.get_mut(account_id) {
+            Some(trashed) => trashed,
+            None => return 0,
+        };
+        let deadline = env::block_timestamp();
+        let grace_period = self.deletion_grace_period_ns;
+        let expired: Vec<String> = trashed.iter()
+            .filter(|(_, (_, deleted_at))| deleted_at.saturating_add(grace_period) <= deadline)
+            .map(|(resource, _)| resource.clone())
+            .collect();
+
+        let mut purged = vec![];
+        for resource in expired {
+            let (key, _) = trashed.remove(&resource).unwrap();
+            purged.push((resource, key.shares));
+        }
+        if trashed.is_empty() {
+            self.trash.remove(account_id);
+        }
+        for (resource, shares) in &purged {
+            self.unindex_shares(account_id, resource, shares);
+        }
+        purged.len() as u64
+    }
+
+    // Stores a value generated by generate_with_options along with the options used
+    fn store_generated(&mut self, account_id: String, resource: String, identifier: String, password: String, options: &GenerationOptions) {
+        let params = serde_json::to_string(options).unwrap();
+        self.store_key(account_id.clone(), resource.clone(), identifier, password);
+        self.update_key(&account_id, &resource, |key| key.generation_params = Some(params));
+    }
+
+    // Replaces the value of an existing key with a generated password that differs from the current
+    // value and every value in its history
+    fn regenerate_key(&mut self, rng: &mut StdRng, account_id: String, resource: String, length: u32, classes: &[(&str, u32)]) {
+        let key = self.existing_key(&account_id, &resource);
+        let identifier = key.identifier.clone();
+        let mut used: Vec<&str> = key.history.iter().map(|(_, value)| value.as_str()).collect();
+        used.push(&key.enc_password);
+
+        validate_generation_params(length, classes);
+        let password = generate_distinct_password(rng, length, classes, &used, MAX_GENERATION_ATTEMPTS);
+        warn_if_trivial(&password, &account_id, &identifier, &resource);
+        self.store_key(account_id, resource, identifier, password);
+    }
+
+    // Removes a key, or moves it to the trash while there is a deletion grace period
+    fn delete_key(&mut self, account_id: String, resource: String) {
+        self.assert_allowed(&account_id);
+        self.purge_expired_trash(&account_id);
+        let resource = self.resource_name(&account_id, resource);
+        let key = match self.keys.get_mut(&account_id).and_then(|record| record.remove(&resource)) {
+            Some(key) => key,
+            None => panic!("No key stored for resource '{}'", resource),
+        };
+        if self.keys[&account_id].is_empty() {
+            self.keys.remove(&account_id);
+        }
+        self.clear_pending_deletion(&account_id, &resource);
+        self.update_checksum(&account_id);
+
+        // Deleting revokes every share, a restored key has to be shared again
+        let mut key = key;
+        let shares = std::mem::take(&mut key.shares);
+        self.unindex_shares(&account_id, &resource, &shares);
+        if self.deletion_grace_period_ns > 0 {
+            // An older deleted version of the same resource is dropped for good
+            let trashed = self.trash.entry(account_id.clone()).or_default();
+            if let Some(stored) = stored_resource(trashed, &resource) {
+                trashed.remove(&stored);
+            }
+            trashed.insert(resource, (key, env::block_timestamp()));
+        }
+    }
+
+    fn clear_pending_deletion(&mut self, record_id: &str, resource: &str) {
+        if let Some(pending) = self.pending_deletions.get_mut(record_id) {
+            pending.remove(resource);
+            if pending.is_empty() {
+                self.pending_deletions.remove(record_id);
+            }
+        }
+    }
+
+    // Stores a value the client supplied, which unlike a generated one may be weaker than the value
+    // it replaces: `force` skips the strength guard of the record's owner. Its panic doesn't say how
+    // strong either value is, since the new one may be a candidate the caller is probing.
+    fn store_client_value(&mut self, account_id: String, resource: String, identifier: String, enc_password: String, force: bool) {
+        if !force && self.strength_guarded.iter().any(|guarded| guarded == record_owner(&account_id)) {
+            if let Some(key) = self.key(&account_id, &resource) {
+                assert!(
+                    strength_score(&enc_password) >= strength_score(&key.enc_password),
+                    "New password is weaker than the current one, pass force to replace it anyway"
+                );
+            }
+        }
+        self.store_key(account_id, resource, identifier, enc_password);
+    }
+
+    // Inserts a new key or replaces the value and identifier of an existing one, keeping its metadata
+    fn store_key(&mut self, account_id: String, resource: String, identifier: String, enc_password: String) {
+        self.assert_allowed(&account_id);
+        let identifier = match self.default_identifiers.get(record_owner(&account_id)) {
+            Some(default) if identifier.is_empty() => default.clone(),
+            _ => identifier,
+        };
+        assert_key_names(&resource, &identifier);
+        self.purge_expired_trash(&account_id);
+        let resource = self.resource_name(&account_id, resource);
+        let max_credentials = self.max_credentials_per_account;
+        let stored = match self.key(&account_id, &resource) {
+            Some(_) => 0,
+            None => self.credential_count(record_owner(&account_id)),
+        };
+        let record = self.keys.entry(account_id.clone()).or_default();
+        match record.get_mut(&resource) {
+            Some(key) => {
+                key.identifier = identifier;
+                if key.enc_password != enc_password {
+                    key.algorithm = DEFAULT_ALGORITHM.to_string();
+                    key.generation_params = None;
+                    let previous = std::mem::replace(&mut key.enc_password, enc_password);
+                    key.history.push((env::block_timestamp(), previous));
+                    key.rotation_count += 1;
+                    if key.history.len() > MAX_HISTORY {
+                        key.history.remove(0);
+                    }
+                }
+                key.updated_at = env::block_timestamp();
+            }
+            None => {
+                assert_credential_cap(stored + 1, max_credentials);
+                let seq = self.next_creation_seq.entry(record_owner(&account_id).to_string()).or_default();
+                let mut key = Key::new(identifier, enc_password);
+                key.creation_seq = *seq;
+                *seq += 1;
+                record.insert(resource, key);
+            }
+        }
+        self.update_checksum(&account_id);
+    }
+
+    fn existing_key(&self, account_id: &str, resource: &str) -> &Key {
+        match self.key(account_id, resource) {
+            Some(key) => key,
+            None => panic!("No key stored for resource '{}'", resource),
+        }
+    }
+
+    // Modifies an existing key in place and refreshes the account checksum
+    fn update_key<R>(&mut self, account_id: &str, resource: &str, update: impl FnOnce(&mut Key) -> R) -> R {
+        self.assert_allowed(account_id);
+        let resource = self.resource_name(account_id, resource.to_string());
+        let key = match self.keys.get_mut(account_id).and_then(|record| record.get_mut(&resource)) {
+            Some(key) => key,
+            None => panic!("No key stored for resource '{}'", resource),
+        };
+        let result = update(key);
+        self.update_checksum(account_id);
+        result
+    }
+
+    // The rng for the next generation, advancing the counter in DeterministicCounter mode
+    fn next_rng(&mut self) -> StdRng {
+        if self.entropy_mode == EntropyMode::DeterministicCounter {
+            self.entropy_counter += 1;
+        }
+        self.rng_at(self.entropy_counter)
+    }
+
+    fn rng_at(&self, counter: u64) -> StdRng {
+        match self.entropy_mode {
+            EntropyMode::BlockchainRandom => seeded_rng(),
+            EntropyMode::DeterministicCounter => counter_rng(counter),
+        }
+    }
+
+    // Every state-changing call starts here. Reads, including access_password's last-accessed
+    // bookkeeping, don't count as activity.
+    fn touch(&mut self) {
+        self.last_write_timestamp = env::block_timestamp();
+        self.keep_unlocked();
+    }
+
+    // Any transaction from an unlocked account restarts its auto-lock window, reads sent as one too
+    fn keep_unlocked(&mut self) {
+        let account_id = env::signer_account_id();
+        if self.auto_lock_ns.contains_key(&account_id) && !self.is_locked(account_id.clone()) {
+            self.active_at.insert(account_id, env::block_timestamp());
+        }
+    }
+
+    // Every write to an account's keys ends here
+    fn update_checksum(&mut self, account_id: &str) {
+        match self.keys.get(account_id) {
+            Some(record) => {
+                let checksum = record_checksum(record);
+                self.checksums.insert(account_id.to_string(), checksum);
+            }
+            None => {
+                self.checksums.remove(account_id);
+            }
+        }
+    }
+}
+
+/*
+ * The rest of this file holds the inline tests for the code above
+ * Learn more about Rust tests: https://doc.rust-lang.org/book/ch11-01-writing-tests.html
+ *
+ * To run from contract directory:
+ * cargo test -- --nocapture
+ *
+ * From project root, to run in combination with frontend tests:
+ * yarn test
+ *
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+
+    // mock the context for testing, notice "signer_account_id" that was accessed above from env::
+    fn get_context(input: Vec<u8>, is_view: bool) -> VMContext {
+        VMContext {
+            current_account_id: "alice_near".to_string(),
+            signer_account_id: "bob_near".to_string(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id: "carol_near".to_string(),
+            input,
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 0,
+            account_locked_balance: 0,
+            storage_usage: 0,
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10,
+                              11, 12, 13, 14, 15, 16, 17, 18, 19,
+                              20, 21, 22, 23, 24, 25, 26, 27, 28,
+                              29, 30, 31],
+            is_view,
+            output_data_receivers: vec![],
+            epoch_height: 19,
+        }
+    }
+
+    // Runs `f` with the owner (the contract account) as predecessor, keeping the block's timestamp
+    // and seed, then switches back to the default signer and predecessor
+    fn as_owner<R>(contract: &mut Keychain, f: impl FnOnce(&mut Keychain) -> R) -> R {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = env::block_timestamp();
+        context.random_seed = env::random_seed();
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let result = f(contract);
+        context.predecessor_account_id = get_context(vec![], false).predecessor_account_id;
+        testing_env!(context);
+        result
+    }
+
+    // A fresh contract the owner first configured through `setup`
+    fn owned_contract(setup: impl FnOnce(&mut Keychain)) -> Keychain {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Keychain::default();
+        as_owner(&mut contract, setup);
+        contract
+    }
+
+    // Logs emitted on this test's thread so far
+    fn get_logs() -> Vec<String> {
+        TEST_LOGS.with(|logs| logs.borrow().clone())
+    }
+
+    // What a frontend passes in place of a secret token
+    fn sha256_hex(value: &str) -> String {
+        to_hex(&env::sha256(value.as_bytes()))
+    }
+
+    fn insert_key(contract: &mut Keychain, account_id: &str, resource: &str, password: &str) {
+        contract.keys.entry(account_id.to_string()).or_default()
+            .insert(resource.to_string(), Key::new("bob@email.com".to_string(), password.to_string()));
+    }
+
+    #[test]
+    fn migrate_preserves_legacy_keys() {
+        let mut context = get_context(vec![], false);
+        context.predecessor_account_id = "alice_near".to_string();
+        context.block_timestamp = 42;
+        testing_env!(context);
+
+        let mut record = HashMap::new();
+        record.insert("email".to_string(), LegacyKey { identifier: "bob@email.com".to_string(), enc_password: "secret".to_string() });
+        record.insert("bank".to_string(), LegacyKey { identifier: "bob".to_string(), enc_password: "hunter2".to_string() });
+        let mut keys = HashMap::new();
+        keys.insert("bob_near".to_string(), record);
+        env::state_write(&LegacyKeychain { keys });
+
+        let contract = Keychain::migrate();
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert_eq!("hunter2", contract.get_password(&"bob_near".to_string(), &"bank".to_string()));
+        let key = contract.key("bob_near", "bank").unwrap();
+        assert_eq!("bob", key.identifier);
+        assert_eq!(42, key.updated_at);
+        assert!(key.custom_fields.is_empty() && key.shares.is_empty() && key.history.is_empty());
+
+        testing_env!(get_context(vec![], false));
+        let mut contract = contract;
+        contract.set_password("aaa".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!(vec!["bank", "email", "aaa"], contract.list_by_creation("bob_near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract account can migrate its state")]
+    fn migrate_is_restricted_to_contract_account() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        Keychain::migrate();
+    }
+
+    #[test]
+    fn generate_then_check_password_length() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("email".to_string(), "bob@email.com".to_string());
+        assert_eq!(
+            12,
+            contract.get_password(&"bob_near".to_string(), &"email".to_string()).len()
+        );
+    }
+
+    #[test]
+    fn get_default_key() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let contract = Keychain::default();
+        // this test did not store a key so should return the default empty password
+        assert_eq!(
+            "".to_string(),
+            contract.get_password(&"francis.near".to_string(), &"".to_string())
+        );
+    }
+
+    #[test]
+    fn owner_and_grantee_can_read_password() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], None);
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"vpn".to_string()));
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"vpn".to_string()));
+        assert_eq!(Some("secret".to_string()), contract.get_password_opt("bob_near".to_string(), "vpn".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'erin_near' is not allowed to read 'vpn' of 'bob_near'")]
+    fn third_party_cannot_read_password() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], None);
+
+        context.signer_account_id = "erin_near".to_string();
+        testing_env!(context);
+        contract.get_password(&"bob_near".to_string(), &"vpn".to_string());
+    }
+
+    #[test]
+    fn get_password_verified_requires_matching_fingerprint() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "c2VjcmV0".to_string());
+        let get = |contract: &Keychain, fingerprint: &str| {
+            contract.get_password_verified("bob_near".to_string(), "email".to_string(), fingerprint.to_string())
+        };
+        assert_eq!(None, get(&contract, ""));
+
+        contract.set_key_fingerprint("sha256:4f2a9c".to_string());
+        assert_eq!(Some("c2VjcmV0".to_string()), get(&contract, "sha256:4f2a9c"));
+        assert_eq!(None, get(&contract, "sha256:000000"));
+        assert!(!contract.try_to_vec().unwrap().windows(6).any(|window| window == b"4f2a9c"));
+    }
+
+    #[test]
+    fn get_password_opt_distinguishes_empty_from_missing() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "".to_string());
+        assert_eq!(Some("".to_string()), contract.get_password_opt("bob_near".to_string(), "email".to_string()));
+        assert_eq!(None, contract.get_password_opt("bob_near".to_string(), "bank".to_string()));
+
+        // An explicitly stored empty value isn't overwritten by generation either
+        contract.generate_new_password("email".to_string(), "bob".to_string());
+        assert_eq!("", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+    }
+
+    #[test]
+    fn version_info_reports_version_and_features() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let contract = Keychain::default();
+        let (version, features) = contract.version_info();
+        assert_eq!("0.1.0", version);
+        assert!(features.contains(&"vaults".to_string()));
+        assert!(!features.contains(&"totp".to_string()));
+    }
+
+    #[test]
+    fn version_info_lists_every_public_feature() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let (_, features) = Keychain::default().version_info();
+        assert_eq!(
+            vec![
+                "sharing", "vaults", "teams", "trash", "history", "tags", "attachments", "notes", "backup_codes",
+                "site_rules", "archiving", "emergency_access", "borsh_backup", "keepass_import", "custom_fields",
+                "urls", "security_questions", "auto_rotation", "clipboard_clear", "key_fingerprints", "strength_guard",
+                "account_merge", "two_phase_delete", "access_tracking", "reminders", "reveal_tokens", "two_factor",
+                "auto_lock",
+            ],
+            features
+        );
+    }
+
+    #[test]
+    fn last_activity_advances_on_writes() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        assert_eq!(0, contract.last_activity());
+
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!(1_000, contract.last_activity());
+
+        context.block_timestamp = 5_000;
+        testing_env!(context.clone());
+        contract.delete_password("email".to_string());
+        assert_eq!(5_000, contract.last_activity());
+
+        context.block_timestamp = 6_000;
+        testing_env!(context.clone());
+        contract.set_default_identifier("bob@email.com".to_string());
+        assert_eq!(6_000, contract.last_activity());
+
+        context.block_timestamp = 7_000;
+        testing_env!(context);
+        contract.generate_with_requirements("bank".to_string(), "bob".to_string(), 16, true, 1, String::new());
+        assert_eq!(7_000, contract.last_activity());
+    }
+
+    #[test]
+    fn reads_are_not_activity() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+
+        context.block_timestamp = 5_000;
+        testing_env!(context);
+        contract.access_password("bob_near".to_string(), "email".to_string());
+        assert_eq!(1_000, contract.last_activity());
+    }
+
+    #[test]
+    fn integrity_holds_across_writes_and_deletes() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        assert!(contract.verify_integrity("bob_near".to_string()));
+
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.generate_new_password("bank".to_string(), "bob".to_string());
+        contract.set_custom_field("bank".to_string(), "pin_hint".to_string(), "birthday".to_string());
+        contract.share_with_many("bank".to_string(), vec!["dave_near".to_string()], None);
+        assert!(contract.verify_integrity("bob_near".to_string()));
+
+        contract.delete_password("email".to_string());
+        assert!(contract.verify_integrity("bob_near".to_string()));
+        contract.delete_password("bank".to_string());
+        assert!(contract.verify_integrity("bob_near".to_string()));
+        assert!(!contract.keys.contains_key("bob_near"));
+    }
+
+    #[test]
+    fn replace_identifier_domain_only_touches_matching_identifiers() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob@OldCorp.com".to_string(), "s1".to_string());
+        contract.set_password("vpn".to_string(), "bob.smith@oldcorp.com".to_string(), "s2".to_string());
+        contract.set_password("forum".to_string(), "bob@gmail.com".to_string(), "s3".to_string());
+        contract.set_password("bank".to_string(), "bob@notoldcorp.com".to_string(), "s4".to_string());
+
+        let changed = contract.replace_identifier_domain("oldcorp.com".to_string(), "newcorp.io".to_string());
+        assert_eq!(2, changed);
+        let identifier = |resource: &str| contract.key("bob_near", resource).unwrap().identifier.clone();
+        assert_eq!("bob@newcorp.io", identifier("email"));
+        assert_eq!("bob.smith@newcorp.io", identifier("vpn"));
+        assert_eq!("bob@gmail.com", identifier("forum"));
+        assert_eq!("bob@notoldcorp.com", identifier("bank"));
+        assert!(contract.verify_integrity("bob_near".to_string()));
+    }
+
+    #[test]
+    fn confirm_delete_within_window_removes_key() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.request_delete("Email".to_string());
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+
+        context.block_timestamp = 1_000 + DELETE_CONFIRMATION_WINDOW_NS;
+        testing_env!(context);
+        contract.confirm_delete("email".to_string());
+        assert_eq!(None, contract.get_password_opt("bob_near".to_string(), "email".to_string()));
+        assert!(contract.pending_deletions.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The deletion request for resource 'email' has expired")]
+    fn confirm_delete_after_window_fails() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.request_delete("email".to_string());
+
+        context.block_timestamp = 1_001 + DELETE_CONFIRMATION_WINDOW_NS;
+        testing_env!(context);
+        contract.confirm_delete("email".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "No deletion was requested for resource 'email'")]
+    fn confirm_delete_requires_request() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.confirm_delete("email".to_string());
+    }
+
+    #[test]
+    fn soft_deleted_key_is_hidden_and_restorable() {
+        let mut contract = owned_contract(|c| c.set_deletion_grace_period(100));
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.delete_password("email".to_string());
+        assert_eq!("", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert_eq!(None, contract.get_password_opt("bob_near".to_string(), "email".to_string()));
+
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 99;
+        testing_env!(context);
+        contract.restore_password("email".to_string());
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert!(contract.verify_integrity("bob_near".to_string()));
+    }
+
+    #[test]
+    fn soft_deleted_key_is_purged_after_grace_period() {
+        let mut contract = owned_contract(|c| c.set_deletion_grace_period(100));
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.delete_password("email".to_string());
+
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 100;
+        testing_env!(context);
+        assert_eq!(1, contract.purge_deleted());
+        assert!(contract.trash.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "No deleted key to restore for resource 'email'")]
+    fn soft_deleted_key_cannot_be_restored_after_grace_period() {
+        let mut contract = owned_contract(|c| c.set_deletion_grace_period(100));
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.delete_password("email".to_string());
+
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 150;
+        testing_env!(context);
+        contract.restore_password("email".to_string());
+    }
+
+    #[test]
+    fn compact_removes_prunable_data() {
+        let mut contract = owned_contract(|c| c.set_deletion_grace_period(100));
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("vpn".to_string(), "team".to_string(), "hunter2".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], Some(50));
+        contract.delete_password("email".to_string());
+
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 200;
+        testing_env!(context);
+        assert!(contract.compact() > 0);
+        assert!(contract.trash.is_empty());
+        assert!(contract.shared_with.is_empty());
+        let key = contract.key("bob_near", "vpn").unwrap();
+        assert!(key.shares.is_empty());
+        assert!(contract.verify_integrity("bob_near".to_string()));
+        assert_eq!(0, contract.compact());
+    }
+
+    #[test]
+    fn delete_without_grace_period_is_permanent() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.delete_password("email".to_string());
+        assert!(contract.trash.is_empty());
+        assert_eq!(None, contract.get_password_opt("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    fn corrupted_state_fails_integrity_check() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+
+        contract.keys.get_mut("bob_near").unwrap().get_mut("email").unwrap().enc_password = "corrupted".to_string();
+        assert!(!contract.verify_integrity("bob_near".to_string()));
+    }
+
+    #[test]
+    fn age_histogram_buckets_by_last_update() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for (resource, created_at) in &[
+            ("email", 0),
+            ("bank", 200 * NANOS_PER_DAY),
+            ("forum", 340 * NANOS_PER_DAY),
+            ("vpn", 380 * NANOS_PER_DAY),
+            ("wiki", 395 * NANOS_PER_DAY),
+        ] {
+            context.block_timestamp = *created_at;
+            testing_env!(context.clone());
+            contract.generate_new_password(resource.to_string(), "bob@email.com".to_string());
+        }
+
+        context.block_timestamp = 400 * NANOS_PER_DAY;
+        testing_env!(context);
+        assert_eq!([2, 1, 1, 1], contract.age_histogram("bob_near".to_string()));
+        assert_eq!([0, 0, 0, 0], contract.age_histogram("francis.near".to_string()));
+    }
+
+    #[test]
+    fn archived_keys_are_listed_separately_and_stay_readable() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("fax".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.archive_resource("Fax".to_string());
+
+        assert_eq!(vec!["email".to_string()], contract.list_resources("bob_near".to_string()));
+        assert_eq!(vec!["fax".to_string()], contract.list_archived("bob_near".to_string()));
+        assert_eq!("hunter2", contract.get_password(&"bob_near".to_string(), &"fax".to_string()));
+
+        contract.unarchive_resource("fax".to_string());
+        assert_eq!(vec!["email".to_string(), "fax".to_string()], contract.list_resources("bob_near".to_string()));
+        assert!(contract.list_archived("bob_near".to_string()).is_empty());
+    }
+
+    #[test]
+    fn list_by_creation_keeps_insertion_order() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        for resource in ["wiki", "bank", "email"] {
+            contract.set_password(resource.to_string(), "bob".to_string(), "secret".to_string());
+        }
+        contract.set_password("bank".to_string(), "bob".to_string(), "rotated".to_string());
+        assert_eq!(
+            vec!["wiki".to_string(), "bank".to_string(), "email".to_string()],
+            contract.list_by_creation("bob_near".to_string())
+        );
+    }
+
+    #[test]
+    fn most_recently_accessed_orders_by_last_read() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for resource in ["email", "bank", "vpn", "wiki"] {
+            contract.set_password(resource.to_string(), "bob".to_string(), "secret".to_string());
+        }
+        for (timestamp, resource) in [(100, "bank"), (200, "email"), (300, "vpn"), (400, "Bank")] {
+            context.block_timestamp = timestamp;
+            testing_env!(context.clone());
+            assert_eq!(Some("secret".to_string()), contract.access_password("bob_near".to_string(), resource.to_string()));
+        }
+        assert_eq!(None, contract.access_password("bob_near".to_string(), "missing".to_string()));
+
+        assert_eq!(
+            vec![("bank".to_string(), 400), ("vpn".to_string(), 300)],
+            contract.most_recently_accessed("bob_near".to_string(), 2)
+        );
+        assert_eq!(3, contract.most_recently_accessed("bob_near".to_string(), 10).len());
+        assert!(contract.verify_integrity("bob_near".to_string()));
+    }
+
+    #[test]
+    fn list_recent_orders_by_last_update() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for (resource, updated_at) in &[("email", 30), ("bank", 10), ("forum", 20)] {
+            context.block_timestamp = *updated_at;
+            testing_env!(context.clone());
+            contract.set_password(resource.to_string(), "bob".to_string(), format!("{}-secret", resource));
+        }
+
+        assert_eq!(
+            vec![("email".to_string(), 30), ("forum".to_string(), 20)],
+            contract.list_recent("bob_near".to_string(), 2)
+        );
+        assert_eq!(3, contract.list_recent("bob_near".to_string(), 10).len());
+    }
+
+    #[test]
+    fn resource_names_are_case_insensitive() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("GitHub".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"github".to_string()));
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"GITHUB".to_string()));
+
+        contract.set_password("github".to_string(), "bob".to_string(), "rotated".to_string());
+        assert_eq!(
+            vec!["GitHub".to_string()],
+            contract.list_recent("bob_near".to_string(), 10).into_iter().map(|(resource, _)| resource).collect::<Vec<_>>()
+        );
+        assert_eq!(Some("secret".to_string()), contract.get_historical_password("bob_near".to_string(), "gitHUB".to_string(), 1));
+
+        contract.delete_password("GITHUB".to_string());
+        assert_eq!(None, contract.get_password_opt("bob_near".to_string(), "GitHub".to_string()));
+    }
+
+    #[test]
+    fn restored_key_keeps_display_casing() {
+        let mut contract = owned_contract(|c| c.set_deletion_grace_period(100));
+        contract.set_password("GitHub".to_string(), "bob".to_string(), "secret".to_string());
+        contract.share_with_many("github".to_string(), vec!["dave_near".to_string()], None);
+        contract.delete_password("github".to_string());
+        contract.restore_password("GITHUB".to_string());
+        assert!(contract.shared_with_me("dave_near".to_string()).is_empty());
+        assert!(contract.key("bob_near", "GitHub").is_some());
+        assert!(contract.keys["bob_near"].contains_key("GitHub"));
+    }
+
+    #[test]
+    fn vaults_hold_independent_keys() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "personal-secret".to_string());
+        contract.set_password_in_vault("work".to_string(), "email".to_string(), "bob@corp.com".to_string(), "work-secret".to_string());
+
+        let get = |contract: &Keychain, vault: &str| {
+            contract.get_password_in_vault("bob_near".to_string(), vault.to_string(), "email".to_string())
+        };
+        assert_eq!(Some("personal-secret".to_string()), get(&contract, ""));
+        assert_eq!(Some("personal-secret".to_string()), get(&contract, "default"));
+        assert_eq!(Some("work-secret".to_string()), get(&contract, "work"));
+        assert_eq!(vec!["default".to_string(), "work".to_string()], contract.list_vaults("bob_near".to_string()));
+
+        contract.delete_password_in_vault("work".to_string(), "email".to_string());
+        assert_eq!(None, get(&contract, "work"));
+        assert_eq!(Some("personal-secret".to_string()), get(&contract, ""));
+        assert_eq!(vec!["default".to_string()], contract.list_vaults("bob_near".to_string()));
+    }
+
+    #[test]
+    fn move_to_vault_relocates_keys() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("vpn".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.set_password("wiki".to_string(), "bob".to_string(), "stays".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], None);
+
+        let moved = contract.move_to_vault(
+            vec!["Email".to_string(), "vpn".to_string(), "missing".to_string()],
+            "".to_string(),
+            "work".to_string(),
+        );
+        assert_eq!(2, moved);
+        assert_eq!(vec!["wiki".to_string()], contract.list_resources("bob_near".to_string()));
+        assert_eq!(
+            Some("secret".to_string()),
+            contract.get_password_in_vault("bob_near".to_string(), "work".to_string(), "email".to_string())
+        );
+        assert!(contract.verify_integrity("bob_near".to_string()));
+        assert!(contract.verify_integrity("bob_near:work".to_string()));
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        assert_eq!(vec![("bob_near:work".to_string(), "vpn".to_string())], contract.shared_with_me("dave_near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource 'email' already exists in vault 'work'")]
+    fn move_to_vault_rejects_collisions() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("vpn".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.set_password_in_vault("work".to_string(), "EMAIL".to_string(), "bob".to_string(), "work-secret".to_string());
+        contract.move_to_vault(vec!["vpn".to_string(), "email".to_string()], "default".to_string(), "work".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "An account can store at most 2 credentials")]
+    fn credential_cap_counts_every_vault() {
+        let mut contract = owned_contract(|c| c.set_max_credentials_per_account(2));
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password_in_vault("work".to_string(), "email".to_string(), "bob".to_string(), "work-secret".to_string());
+        contract.set_password_in_vault("side".to_string(), "email".to_string(), "bob".to_string(), "side-secret".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'dave_near' is not allowed to read 'email' of 'bob_near:work'")]
+    fn vault_keys_are_private() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password_in_vault("work".to_string(), "email".to_string(), "bob".to_string(), "work-secret".to_string());
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.get_password_in_vault("bob_near".to_string(), "work".to_string(), "email".to_string());
+    }
+
+    fn team_contract() -> (Keychain, VMContext) {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.create_team("ops".to_string());
+        contract.add_team_member("ops".to_string(), "dave_near".to_string());
+        contract.add_team_member("ops".to_string(), "erin_near".to_string());
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context.clone());
+        contract.set_team_password("ops".to_string(), "pager".to_string(), "oncall".to_string(), "team-secret".to_string());
+        (contract, context)
+    }
+
+    #[test]
+    fn team_members_share_team_keys() {
+        let (contract, mut context) = team_contract();
+        assert_eq!(
+            vec!["bob_near".to_string(), "dave_near".to_string(), "erin_near".to_string()],
+            contract.get_team_members("ops".to_string())
+        );
+        for member in ["bob_near", "dave_near", "erin_near"].iter() {
+            context.signer_account_id = member.to_string();
+            testing_env!(context.clone());
+            assert_eq!(Some("team-secret".to_string()), contract.get_team_password("ops".to_string(), "pager".to_string()));
+        }
+        assert!(!contract.keys.contains_key("dave_near"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'frank_near' is not a member of team 'ops'")]
+    fn non_members_cannot_read_team_keys() {
+        let (contract, mut context) = team_contract();
+        context.signer_account_id = "frank_near".to_string();
+        testing_env!(context);
+        contract.get_team_password("ops".to_string(), "pager".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner of team 'ops' can change its members")]
+    fn only_team_owner_adds_members() {
+        let (mut contract, _) = team_contract();
+        contract.add_team_member("ops".to_string(), "frank_near".to_string());
+    }
+
+    #[test]
+    fn timestamp_bounds_spans_oldest_to_newest() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        assert_eq!(None, contract.timestamp_bounds("bob_near".to_string()));
+        for (resource, updated_at) in &[("email", 30), ("bank", 10), ("forum", 20)] {
+            context.block_timestamp = *updated_at;
+            testing_env!(context.clone());
+            contract.set_password(resource.to_string(), "bob".to_string(), format!("{}-secret", resource));
+        }
+        assert_eq!(Some((10, 30)), contract.timestamp_bounds("bob_near".to_string()));
+    }
+
+    fn merge_contract(on_conflict: &str) -> (Keychain, u64) {
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "old_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "old@email.com".to_string(), "theirs".to_string());
+        contract.set_password("forum".to_string(), "old".to_string(), "forum-secret".to_string());
+        contract.approve_merge("bob_near".to_string());
+
+        context.signer_account_id = "bob_near".to_string();
+        testing_env!(context);
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "mine".to_string());
+        let moved = contract.merge_from("old_near".to_string(), on_conflict.to_string());
+        (contract, moved)
+    }
+
+    #[test]
+    fn merge_from_keep_mine() {
+        let (contract, moved) = merge_contract("keep_mine");
+        assert_eq!(1, moved);
+        assert_eq!("mine", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert_eq!("forum-secret", contract.get_password(&"bob_near".to_string(), &"forum".to_string()));
+        assert!(!contract.keys.contains_key("old_near"));
+    }
+
+    #[test]
+    fn merge_from_take_theirs() {
+        let (contract, moved) = merge_contract("take_theirs");
+        assert_eq!(2, moved);
+        assert_eq!("theirs", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert_eq!("forum-secret", contract.get_password(&"bob_near".to_string(), &"forum".to_string()));
+        assert!(!contract.keys.contains_key("old_near"));
+    }
+
+    #[test]
+    fn merge_from_skip() {
+        let (contract, moved) = merge_contract("skip");
+        assert_eq!(1, moved);
+        assert_eq!("mine", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert_eq!("forum-secret", contract.get_password(&"bob_near".to_string(), &"forum".to_string()));
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "old_near".to_string();
+        testing_env!(context);
+        assert_eq!("theirs", contract.get_password(&"old_near".to_string(), &"email".to_string()));
+    }
+
+    #[test]
+    fn merged_keys_are_created_after_the_targets_own() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("zeta".to_string(), "bob".to_string(), "secret".to_string());
+        context.signer_account_id = "old_near".to_string();
+        testing_env!(context.clone());
+        contract.set_password("beta".to_string(), "old".to_string(), "secret".to_string());
+        contract.set_password("alpha".to_string(), "old".to_string(), "secret".to_string());
+        contract.approve_merge("bob_near".to_string());
+
+        context.signer_account_id = "bob_near".to_string();
+        testing_env!(context);
+        contract.merge_from("old_near".to_string(), "skip".to_string());
+        contract.set_password("new".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!(vec!["zeta", "beta", "alpha", "new"], contract.list_by_creation("bob_near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "'old_near' has not approved merging into 'bob_near'")]
+    fn merge_from_requires_approval() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.merge_from("old_near".to_string(), "skip".to_string());
+    }
+
+    #[test]
+    fn set_then_get_custom_fields() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("server".to_string(), "root".to_string());
+        contract.set_custom_field("server".to_string(), "ip".to_string(), "10.0.0.1".to_string());
+        contract.set_custom_field("server".to_string(), "pin_hint".to_string(), "birthday".to_string());
+        contract.set_custom_field("server".to_string(), "ip".to_string(), "10.0.0.2".to_string());
+        assert_eq!(
+            vec![
+                ("pin_hint".to_string(), "birthday".to_string()),
+                ("ip".to_string(), "10.0.0.2".to_string()),
+            ],
+            contract.get_custom_fields("bob_near".to_string(), "server".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'dave_near' is not allowed to read 'server' of 'bob_near'")]
+    fn custom_fields_are_private() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.generate_new_password("server".to_string(), "root".to_string());
+        contract.set_custom_field("server".to_string(), "pin".to_string(), "1234".to_string());
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.get_custom_fields("bob_near".to_string(), "server".to_string());
+    }
+
+    #[test]
+    fn audit_entropy_flags_weak_passwords() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "pin", "1234");
+        insert_key(&mut contract, "bob_near", "email", "x7#Kp9!qLm2$Vw4z");
+        assert_eq!(vec!["pin".to_string()], contract.audit_entropy("bob_near".to_string(), 60));
+        assert!(contract.audit_entropy("bob_near".to_string(), 10).is_empty());
+    }
+
+    #[test]
+    fn verify_share_indexes_detects_drift() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        contract.share_with_many("email".to_string(), vec!["dave_near".to_string(), "erin_near".to_string()], None);
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], None);
+
+        let verify = |contract: &mut Keychain| as_owner(contract, |c| c.verify_share_indexes());
+        assert!(verify(&mut contract));
+
+        // Forward grant without its reverse entry
+        contract.shared_with.get_mut("erin_near").unwrap().clear();
+        assert!(!verify(&mut contract));
+        contract.shared_with.remove("erin_near");
+        assert!(!verify(&mut contract));
+        contract.index_share("erin_near".to_string(), "bob_near", "email");
+        assert!(verify(&mut contract));
+
+        // Reverse entry left behind by a removed grant
+        contract.keys.get_mut("bob_near").unwrap().get_mut("vpn").unwrap().shares.clear();
+        assert!(!verify(&mut contract));
+    }
+
+    #[test]
+    fn find_org_reuse_groups_identical_values_across_accounts() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "email", "Summer2024!");
+        insert_key(&mut contract, "dave_near", "vpn", "Summer2024!");
+        insert_key(&mut contract, "dave_near", "bank", "x7#Kp9!qLm2$Vw4z");
+        insert_key(&mut contract, "erin_near", "bank", "hunter2");
+        insert_key(&mut contract, "erin_near", "wiki", "hunter2");
+        insert_key(&mut contract, "erin_near", "forum", "hunter2");
+        let pair = |account_id: &str, resource: &str| (account_id.to_string(), resource.to_string());
+        assert_eq!(
+            vec![
+                vec![pair("bob_near", "email"), pair("dave_near", "vpn")],
+                vec![pair("erin_near", "bank"), pair("erin_near", "forum"), pair("erin_near", "wiki")],
+            ],
+            as_owner(&mut contract, |c| c.find_org_reuse())
+        );
+    }
+
+    #[test]
+    fn password_strength_scores_entropy_and_patterns() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "pin", "4821");
+        insert_key(&mut contract, "bob_near", "keyboard", "Qwerty12!");
+        insert_key(&mut contract, "bob_near", "email", "x7#Kp9!qLm2$Vw4zR8&n");
+        let strength = |resource: &str| contract.password_strength("bob_near".to_string(), resource.to_string());
+        assert_eq!(13, strength("pin"));
+        assert!(strength("keyboard") < 40);
+        assert_eq!(100, strength("email"));
+        assert_eq!(0, strength("missing"));
+    }
+
+    #[test]
+    fn has_personal_pattern_flags_dates_and_phone_numbers() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        for (resource, password) in [("birthday", "01011990"), ("phone", "5551234567"), ("date", "Born01/01/1990"), ("dashed", "01-01-1990!")] {
+            insert_key(&mut contract, "bob_near", resource, password);
+            assert!(contract.has_personal_pattern("bob_near".to_string(), resource.to_string()), "{} not flagged", password);
+        }
+        for (resource, password) in [("email", "x7#Kp9!qLm2$Vw4z"), ("pin", "4821"), ("mixed", "0101/1990"), ("nine", "123456789")] {
+            insert_key(&mut contract, "bob_near", resource, password);
+            assert!(!contract.has_personal_pattern("bob_near".to_string(), resource.to_string()), "{} flagged", password);
+        }
+    }
+
+    #[test]
+    fn average_strength_is_the_mean_score() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        assert_eq!(0, contract.average_strength("bob_near".to_string()));
+
+        insert_key(&mut contract, "bob_near", "pin", "4821");
+        insert_key(&mut contract, "bob_near", "email", "x7#Kp9!qLm2$Vw4zR8&n");
+        let average = contract.average_strength("bob_near".to_string());
+        assert!(13 < average && average < 100);
+        assert_eq!((13 + 100) / 2, average);
+    }
+
+    #[test]
+    fn weakest_first_orders_by_ascending_strength() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "email", "x7#Kp9!qLm2$Vw4zR8&n");
+        insert_key(&mut contract, "bob_near", "pin", "4821");
+        insert_key(&mut contract, "bob_near", "forum", "password123");
+        insert_key(&mut contract, "bob_near", "bank", "Tr0ub4dor&3x");
+
+        let all = contract.weakest_first("bob_near".to_string(), 10);
+        assert_eq!(4, all.len());
+        assert!(all.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        assert_eq!(("email".to_string(), 100), all[3]);
+
+        let worst = contract.weakest_first("bob_near".to_string(), 2);
+        assert_eq!(all[..2].to_vec(), worst);
+        assert!(contract.weakest_first("bob_near".to_string(), 0).is_empty());
+    }
+
+    #[test]
+    fn reuse_ratio_drops_with_reused_values() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        assert_eq!(100, contract.reuse_ratio("bob_near".to_string()));
+
+        insert_key(&mut contract, "bob_near", "email", "hunter2");
+        insert_key(&mut contract, "bob_near", "bank", "correct horse");
+        assert_eq!(100, contract.reuse_ratio("bob_near".to_string()));
+
+        insert_key(&mut contract, "bob_near", "forum", "hunter2");
+        insert_key(&mut contract, "bob_near", "wiki", "hunter2");
+        assert_eq!(50, contract.reuse_ratio("bob_near".to_string()));
+    }
+
+    #[test]
+    fn erase_account_leaves_no_trace() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password_in_vault("work".to_string(), "vpn".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.share_with_many("email".to_string(), vec!["dave_near".to_string()], None);
+        contract.set_default_identifier("bob@email.com".to_string());
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context.clone());
+        contract.set_password("wiki".to_string(), "dave".to_string(), "dave-secret".to_string());
+        contract.share_with_many("wiki".to_string(), vec!["bob_near".to_string()], None);
+        contract.approve_merge("bob_near".to_string());
+
+        assert_eq!(2, as_owner(&mut contract, |c| c.erase_account("bob_near".to_string())));
+
+        assert_eq!(vec!["dave_near".to_string()], contract.keys.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(vec!["dave_near".to_string()], contract.checksums.keys().cloned().collect::<Vec<_>>());
+        assert!(contract.keys["dave_near"]["wiki"].shares.is_empty());
+        assert!(contract.shared_with.is_empty());
+        assert!(contract.merge_approvals.is_empty());
+        assert!(contract.default_identifiers.is_empty());
+        assert!(contract.verify_integrity("dave_near".to_string()));
+    }
+
+    #[test]
+    fn erase_account_drops_consents_naming_it() {
+        let mut contract = owned_contract(|_| {});
+        contract.consent_to_emergency_access(Some("sos_near".to_string()));
+        as_owner(&mut contract, |c| c.erase_account("sos_near".to_string()));
+        assert_eq!(None, contract.get_emergency_consent("bob_near".to_string()));
+    }
+
+    #[test]
+    fn erase_account_hands_over_or_erases_owned_teams() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.create_team("ops".to_string());
+        contract.add_team_member("ops".to_string(), "dave_near".to_string());
+        contract.set_team_password("ops".to_string(), "vpn".to_string(), "ops".to_string(), "hunter2".to_string());
+        contract.create_team("solo".to_string());
+        contract.set_team_password("solo".to_string(), "wiki".to_string(), "bob".to_string(), "secret".to_string());
+
+        assert_eq!(1, as_owner(&mut contract, |c| c.erase_account("bob_near".to_string())));
+
+        assert_eq!("dave_near", contract.teams["ops"].owner);
+        assert_eq!(vec!["dave_near".to_string()], contract.get_team_members("ops".to_string()));
+        assert!(!contract.teams.contains_key("solo"));
+        assert!(!contract.keys.contains_key(&team_record_id("solo")));
+        assert!(!contract.checksums.contains_key(&team_record_id("solo")));
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        assert_eq!(Some("hunter2".to_string()), contract.get_team_password("ops".to_string(), "vpn".to_string()));
+        contract.add_team_member("ops".to_string(), "erin_near".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn erase_account_is_owner_only() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.erase_account("bob_near".to_string());
+    }
+
+    #[test]
+    fn find_near_duplicates_groups_values_differing_in_trailing_digits() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "email", "Summer2023");
+        insert_key(&mut contract, "bob_near", "bank", "Summer2024");
+        insert_key(&mut contract, "bob_near", "forum", "Summer");
+        insert_key(&mut contract, "bob_near", "vpn", "Winter2024");
+        insert_key(&mut contract, "bob_near", "pin", "12345");
+        insert_key(&mut contract, "bob_near", "phone", "12346");
+        insert_key(&mut contract, "bob_near", "wiki", "x7#Kp9!qLm2$Vw4z");
+        assert_eq!(
+            vec![vec!["bank".to_string(), "email".to_string(), "forum".to_string()]],
+            contract.find_near_duplicates("bob_near".to_string())
+        );
+    }
+
+    #[test]
+    fn crack_time_estimate_grows_with_length_and_pool() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "pin", "4821");
+        insert_key(&mut contract, "bob_near", "forum", "kqzmwtrbxa");
+        insert_key(&mut contract, "bob_near", "email", "x7#Kp9!qLm2$Vw4z");
+        assert_eq!(0, contract.crack_time_estimate("bob_near".to_string(), "pin".to_string()));
+        assert_eq!(26u64.pow(10) / 10_000_000_000, contract.crack_time_estimate("bob_near".to_string(), "forum".to_string()));
+        assert_eq!(u64::MAX, contract.crack_time_estimate("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    fn short_passwords_lists_values_below_threshold() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "pin", "1234");
+        insert_key(&mut contract, "bob_near", "forum", "hunter22");
+        insert_key(&mut contract, "bob_near", "bank", "x7#Kp9!qLm2$Vw4z");
+        assert_eq!(vec!["forum".to_string(), "pin".to_string()], contract.short_passwords("bob_near".to_string(), 12));
+        assert_eq!(vec!["pin".to_string()], contract.short_passwords("bob_near".to_string(), 8));
+        assert!(contract.short_passwords("bob_near".to_string(), 4).is_empty());
+    }
+
+    #[test]
+    fn password_classes_reports_present_classes() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "forum", "lowercaseonly");
+        insert_key(&mut contract, "bob_near", "bank", "x7#Kp9!qLm2$Vw4z");
+        assert_eq!((true, false, false, false), contract.password_classes("bob_near".to_string(), "forum".to_string()));
+        assert_eq!((true, true, true, true), contract.password_classes("bob_near".to_string(), "bank".to_string()));
+        assert_eq!((false, false, false, false), contract.password_classes("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'dave_near' is not allowed to read 'bank' of 'bob_near'")]
+    fn password_classes_are_private() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "bank", "x7#Kp9!qLm2$Vw4z");
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.password_classes("bob_near".to_string(), "bank".to_string());
+    }
+
+    #[test]
+    fn has_weak_pattern_flags_keyboard_rows_and_runs() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "forum", "qwertyuiop");
+        insert_key(&mut contract, "bob_near", "pin", "123456");
+        insert_key(&mut contract, "bob_near", "wiki", "x9ZYXWq!");
+        insert_key(&mut contract, "bob_near", "bank", "x7#Kp9!qLm2$Vw4z");
+        for resource in ["forum", "pin", "wiki"].iter() {
+            assert!(contract.has_weak_pattern("bob_near".to_string(), resource.to_string()), "{} not flagged", resource);
+        }
+        assert!(!contract.has_weak_pattern("bob_near".to_string(), "bank".to_string()));
+        assert!(!contract.has_weak_pattern("bob_near".to_string(), "missing".to_string()));
+    }
+
+    #[test]
+    fn flag_username_based_matches_identifier_local_part() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "email", "Bob1987!");
+        insert_key(&mut contract, "bob_near", "bank", "x7#Kp9!qLm2$Vw4z");
+        assert_eq!(vec!["email".to_string()], contract.flag_username_based("bob_near".to_string()));
+        insert_key(&mut contract, "bob_near:work", "wiki", "bob-wiki");
+        assert_eq!(vec!["wiki".to_string()], contract.flag_username_based("bob_near:work".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only 'bob_near' can audit its keys")]
+    fn other_accounts_cannot_audit_keys() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        insert_key(&mut contract, "bob_near", "email", "Bob1987!");
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.flag_username_based("bob_near".to_string());
+    }
+
+    #[test]
+    fn empty_identifier_falls_back_to_default() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_default_identifier("bob@email.com".to_string());
+        contract.set_password("email".to_string(), "".to_string(), "secret".to_string());
+        contract.generate_new_password("bank".to_string(), "".to_string());
+        contract.set_password("forum".to_string(), "bobby".to_string(), "hunter2".to_string());
+
+        assert_eq!(
+            vec!["bank".to_string(), "email".to_string()],
+            contract.find_by_identifier("bob_near".to_string(), "bob@email.com".to_string())
+        );
+        assert_eq!("bobby", contract.keys["bob_near"]["forum"].identifier);
+
+        contract.set_default_identifier("".to_string());
+        contract.set_password("wiki".to_string(), "".to_string(), "secret".to_string());
+        assert_eq!("", contract.keys["bob_near"]["wiki"].identifier);
+    }
+
+    #[test]
+    fn find_by_identifier_ignores_case() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "secret".to_string());
+        contract.set_password("forum".to_string(), "Bob@Email.com".to_string(), "hunter2".to_string());
+        contract.set_password("bank".to_string(), "bob123".to_string(), "x7#Kp9!qLm2$".to_string());
+
+        assert_eq!(
+            vec!["email".to_string(), "forum".to_string()],
+            contract.find_by_identifier("bob_near".to_string(), "BOB@email.com".to_string())
+        );
+        assert!(contract.find_by_identifier("bob_near".to_string(), "carol@email.com".to_string()).is_empty());
+    }
+
+    #[test]
+    fn clipboard_clear_defaults_to_none() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!(None, contract.get_clipboard_clear("bob_near".to_string(), "email".to_string()));
+
+        contract.set_clipboard_clear("email".to_string(), Some(30));
+        assert_eq!(Some(30), contract.get_clipboard_clear("bob_near".to_string(), "email".to_string()));
+        contract.set_clipboard_clear("email".to_string(), None);
+        assert_eq!(None, contract.get_clipboard_clear("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Clipboard clear timeout must be between 5 and 600 seconds")]
+    fn clipboard_clear_is_bounded() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_clipboard_clear("email".to_string(), Some(3600));
+    }
+
+    #[test]
+    fn incomplete_entries_lists_missing_identifier_or_url() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("bank".to_string(), "".to_string(), "hunter2".to_string());
+        contract.set_password("forum".to_string(), "bob".to_string(), "correct horse".to_string());
+        contract.set_url("email".to_string(), "https://mail.example.com".to_string());
+        contract.set_url("bank".to_string(), "https://bank.example.com".to_string());
+
+        assert_eq!(vec!["bank".to_string(), "forum".to_string()], contract.incomplete_entries("bob_near".to_string()));
+        assert_eq!("https://mail.example.com", contract.get_url("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    fn security_questions_round_trip() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.set_security_question("bank".to_string(), "First pet?".to_string(), "Rex".to_string());
+        contract.set_security_question("bank".to_string(), "Birth city?".to_string(), "Oslo".to_string());
+        contract.set_security_question("bank".to_string(), "First pet?".to_string(), "Fido".to_string());
+        assert_eq!(
+            vec![("First pet?".to_string(), "Fido".to_string()), ("Birth city?".to_string(), "Oslo".to_string())],
+            contract.get_security_questions("bob_near".to_string(), "bank".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "A key can hold at most 5 security questions")]
+    fn security_questions_are_capped() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+        for n in 0..=MAX_SECURITY_QUESTIONS {
+            contract.set_security_question("bank".to_string(), format!("Question {}?", n), "answer".to_string());
+        }
+    }
+
+    #[test]
+    fn security_questions_and_answers_can_reach_the_byte_limit() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+        let longest = "q".repeat(MAX_SECURITY_QA_BYTES);
+        contract.set_security_question("bank".to_string(), longest.clone(), longest.clone());
+        assert_eq!(vec![(longest.clone(), longest)], contract.get_security_questions("bob_near".to_string(), "bank".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Security questions and answers must be at most 256 bytes")]
+    fn security_answers_are_byte_limited() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.set_security_question("bank".to_string(), "First pet?".to_string(), "a".repeat(MAX_SECURITY_QA_BYTES + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Security questions and answers must be at most 256 bytes")]
+    fn security_questions_are_byte_limited() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.set_security_question("bank".to_string(), "q".repeat(MAX_SECURITY_QA_BYTES + 1), "Rex".to_string());
+    }
+
+    #[test]
+    fn used_backup_codes_are_not_listed() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.add_backup_codes("email".to_string(), vec!["1111-2222".to_string(), "3333-4444".to_string()]);
+        contract.add_backup_codes("email".to_string(), vec!["3333-4444".to_string(), "5555-6666".to_string()]);
+        contract.mark_code_used("email".to_string(), "3333-4444".to_string());
+        assert_eq!(
+            vec!["1111-2222".to_string(), "5555-6666".to_string()],
+            contract.unused_backup_codes("bob_near".to_string(), "email".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "A key can hold at most 32 backup codes")]
+    fn backup_codes_are_capped() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "hunter2".to_string());
+        let codes = (0..=MAX_BACKUP_CODES).map(|n| format!("code-{}", n)).collect();
+        contract.add_backup_codes("email".to_string(), codes);
+    }
+
+    #[test]
+    fn backup_codes_can_reach_the_byte_limit() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "hunter2".to_string());
+        let longest = "7".repeat(MAX_BACKUP_CODE_BYTES);
+        contract.add_backup_codes("email".to_string(), vec![longest.clone()]);
+        assert_eq!(vec![longest], contract.unused_backup_codes("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Backup codes must be non-empty and at most 64 bytes")]
+    fn backup_codes_are_byte_limited() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.add_backup_codes("email".to_string(), vec!["7".repeat(MAX_BACKUP_CODE_BYTES + 1)]);
+    }
+
+    #[test]
+    fn import_keepass_csv_maps_columns() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_keepass_csv(vec![
+            r#""Title","Username","Password","URL","Notes""#.to_string(),
+            r#"email,bob@email.com,s3cret!,https://mail.example.com,"#.to_string(),
+            r#""Bank, main",bob,"pa""ss,word",https://bank.example.com,"PIN 1234, card ""gold""""#.to_string(),
+        ]);
+
+        let bob = || "bob_near".to_string();
+        assert_eq!(2, contract.keys[&bob()].len());
+        assert_eq!("s3cret!", contract.get_password(&bob(), &"email".to_string()));
+        assert_eq!("bob@email.com", contract.key("bob_near", "email").unwrap().identifier);
+        assert_eq!("https://mail.example.com", contract.get_url(bob(), "email".to_string()));
+        assert_eq!("", contract.get_notes(bob(), "email".to_string()));
+        assert_eq!("pa\"ss,word", contract.get_password(&bob(), &"Bank, main".to_string()));
+        assert_eq!("https://bank.example.com", contract.get_url(bob(), "bank, main".to_string()));
+        assert_eq!("PIN 1234, card \"gold\"", contract.get_notes(bob(), "Bank, main".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Row 1 has 3 columns, expected 5")]
+    fn import_keepass_csv_rejects_short_rows() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_keepass_csv(vec!["email,bob,secret".to_string()]);
+    }
+
+    #[test]
+    fn validate_against_domain_applies_site_rules() {
+        let mut contract = owned_contract(|c| {
+            c.set_site_rules("Bank.example".to_string(), SiteRules { max_length: Some(12), forbidden_chars: "<>".to_string() })
+        });
+        contract.set_password("bank".to_string(), "bob".to_string(), "x7#Kp9!qLm2$Vw4z<".to_string());
+        contract.set_url("bank".to_string(), "https://online.bank.example:443/login?next=/".to_string());
+        contract.set_password("forum".to_string(), "bob".to_string(), "x7#Kp9!qLm2$Vw4z".to_string());
+        contract.set_url("forum".to_string(), "https://forum.example".to_string());
+
+        assert_eq!(
+            (false, vec!["too long, at most 12 characters allowed".to_string(), "contains forbidden characters".to_string()]),
+            contract.validate_against_domain("bob_near".to_string(), "bank".to_string())
+        );
+        assert_eq!((true, vec![]), contract.validate_against_domain("bob_near".to_string(), "forum".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'alice_near' is not allowed to read 'bank' of 'bob_near'")]
+    fn owner_cannot_probe_values_through_site_rules() {
+        let mut contract = owned_contract(|_| {});
+        contract.set_password("bank".to_string(), "bob".to_string(), "x7#Kp9!qLm2$Vw4z".to_string());
+        contract.set_url("bank".to_string(), "https://bank.example".to_string());
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "alice_near".to_string();
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context);
+        contract.set_site_rules("bank.example".to_string(), SiteRules { max_length: None, forbidden_chars: "x7#".to_string() });
+        contract.validate_against_domain("bob_near".to_string(), "bank".to_string());
+    }
+
+    #[test]
+    fn url_domain_extracts_host() {
+        assert_eq!("accounts.example.com", url_domain("https://user@Accounts.Example.com:8443/login#top"));
+        assert_eq!("example.com", url_domain("example.com/path"));
+        assert_eq!("", url_domain(""));
+    }
+
+    #[test]
+    fn set_tags_normalizes_and_dedupes() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_tags("email".to_string(), vec!["Work".to_string(), " work ".to_string(), "personal".to_string()]);
+        assert_eq!(
+            vec!["work".to_string(), "personal".to_string()],
+            contract.get_tags("bob_near".to_string(), "email".to_string())
+        );
+    }
+
+    #[test]
+    fn tag_many_skips_missing_resources() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password("vpn".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.set_tags("vpn".to_string(), vec!["work".to_string()]);
+
+        let resources = vec!["email".to_string(), "vpn".to_string(), "missing".to_string()];
+        assert_eq!(2, contract.tag_many(resources, " Work".to_string()));
+        assert_eq!(vec!["work".to_string()], contract.get_tags("bob_near".to_string(), "email".to_string()));
+        assert_eq!(vec!["work".to_string()], contract.get_tags("bob_near".to_string(), "vpn".to_string()));
+        assert!(contract.key("bob_near", "missing").is_none());
+    }
+
+    #[test]
+    fn rename_tag_propagates_and_dedupes() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        for resource in ["email", "vpn", "bank"].iter() {
+            contract.set_password(resource.to_string(), "bob".to_string(), format!("{}-secret", resource));
+        }
+        contract.set_tags("email".to_string(), vec!["job".to_string(), "personal".to_string()]);
+        contract.set_tags("vpn".to_string(), vec!["work".to_string(), "job".to_string()]);
+        contract.set_tags("bank".to_string(), vec!["finance".to_string()]);
+
+        assert_eq!(2, contract.rename_tag("Job".to_string(), "work".to_string()));
+        let tags = |resource: &str| contract.get_tags("bob_near".to_string(), resource.to_string());
+        assert_eq!(vec!["work".to_string(), "personal".to_string()], tags("email"));
+        assert_eq!(vec!["work".to_string()], tags("vpn"));
+        assert_eq!(vec!["finance".to_string()], tags("bank"));
+        assert!(contract.verify_integrity("bob_near".to_string()));
+        assert_eq!(0, contract.rename_tag("job".to_string(), "work".to_string()));
+    }
+
+    #[test]
+    fn list_by_tags_supports_and_or() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        for (resource, tags) in &[("payroll", vec!["work", "finance"]), ("vpn", vec!["work"]), ("bank", vec!["finance"]), ("forum", vec![])] {
+            contract.set_password(resource.to_string(), "bob".to_string(), format!("{}-secret", resource));
+            contract.set_tags(resource.to_string(), tags.iter().map(|tag| tag.to_string()).collect());
+        }
+
+        let tags = vec!["Work".to_string(), "finance".to_string()];
+        assert_eq!(vec!["payroll".to_string()], contract.list_by_tags("bob_near".to_string(), tags.clone(), true));
+        assert_eq!(
+            vec!["bank".to_string(), "payroll".to_string(), "vpn".to_string()],
+            contract.list_by_tags("bob_near".to_string(), tags, false)
+        );
+    }
+
+    #[test]
+    fn tag_counts_orders_by_count_then_name() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        for resource in ["email", "vpn", "bank", "forum", "wiki"].iter() {
+            contract.set_password(resource.to_string(), "bob".to_string(), format!("{}-secret", resource));
+        }
+        contract.set_tags("email".to_string(), vec!["work".to_string(), "personal".to_string()]);
+        contract.set_tags("vpn".to_string(), vec!["work".to_string()]);
+        contract.set_tags("bank".to_string(), vec!["finance".to_string()]);
+
+        assert_eq!(
+            vec![
+                ("untagged".to_string(), 2),
+                ("work".to_string(), 2),
+                ("finance".to_string(), 1),
+                ("personal".to_string(), 1),
+            ],
+            contract.tag_counts("bob_near".to_string())
+        );
+        assert!(contract.tag_counts("dave_near".to_string()).is_empty());
+    }
+
+    #[test]
+    fn share_with_many_grants_each_account() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.generate_new_password("vpn".to_string(), "team@email.com".to_string());
+        let password = contract.get_password(&"bob_near".to_string(), &"vpn".to_string()).to_string();
+        let grantees = vec!["dave_near".to_string(), "erin_near".to_string(), "carol_near".to_string()];
+        contract.share_with_many("vpn".to_string(), grantees.clone(), None);
+
+        assert_eq!(
+            vec!["carol_near".to_string(), "dave_near".to_string(), "erin_near".to_string()],
+            contract.list_shares("vpn".to_string())
+        );
+        for grantee in grantees {
+            context.signer_account_id = grantee;
+            testing_env!(context.clone());
+            assert_eq!(password, contract.get_shared_password("bob_near".to_string(), "vpn".to_string()));
+        }
+    }
+
+    #[test]
+    fn generate_and_share_grants_the_grantee() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.generate_and_share("ci".to_string(), "deploy-bot".to_string(), 20, "dave_near".to_string(), None);
+        contract.set_and_share("vpn".to_string(), "team".to_string(), "ciphertext".to_string(), "dave_near".to_string(), None);
+        let password = contract.get_password(&"bob_near".to_string(), &"ci".to_string()).to_string();
+        assert_eq!(20, password.len());
+        assert_eq!(vec!["dave_near".to_string()], contract.list_shares("ci".to_string()));
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        assert_eq!(password, contract.get_shared_password("bob_near".to_string(), "ci".to_string()));
+        assert_eq!("ciphertext", contract.get_shared_password("bob_near".to_string(), "vpn".to_string()));
+    }
+
+    #[test]
+    fn expiring_shares_lists_shares_within_window() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        contract.set_password("wiki".to_string(), "team".to_string(), "secret".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], Some(1_500));
+        contract.share_with_many("vpn".to_string(), vec!["erin_near".to_string()], Some(9_000));
+        contract.share_with_many("vpn".to_string(), vec!["carol_near".to_string()], None);
+        contract.share_with_many("wiki".to_string(), vec!["dave_near".to_string()], Some(1_200));
+
+        assert_eq!(
+            vec![
+                ("wiki".to_string(), "dave_near".to_string(), 1_200),
+                ("vpn".to_string(), "dave_near".to_string(), 1_500),
+            ],
+            contract.expiring_shares(None, 1_000)
+        );
+        assert_eq!(
+            vec![
+                ("vpn".to_string(), "dave_near".to_string(), 1_500),
+                ("vpn".to_string(), "erin_near".to_string(), 9_000),
+            ],
+            contract.expiring_shares(Some("VPN".to_string()), 10_000)
+        );
+    }
+
+    #[test]
+    fn deleting_a_shared_key_revokes_its_shares() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], None);
+        contract.delete_password("vpn".to_string());
+        assert!(contract.shared_with.is_empty());
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        assert!(contract.shared_with_me("dave_near".to_string()).is_empty());
+        assert_eq!(None, contract.get_password_opt("bob_near".to_string(), "vpn".to_string()));
+    }
+
+    #[test]
+    fn revoke_all_for_grantee_revokes_every_share() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for resource in ["vpn", "wiki", "ci"] {
+            contract.set_password(resource.to_string(), "team".to_string(), "secret".to_string());
+        }
+        contract.set_password_in_vault("work".to_string(), "jira".to_string(), "team".to_string(), "secret".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string(), "erin_near".to_string()], None);
+        contract.share_with_many("wiki".to_string(), vec!["dave_near".to_string()], None);
+        contract.share_with_many("ci".to_string(), vec!["dave_near".to_string()], Some(1));
+
+        assert_eq!(3, contract.revoke_all_for_grantee("dave_near".to_string()));
+        assert_eq!(vec!["erin_near".to_string()], contract.list_shares("vpn".to_string()));
+        assert!(!contract.shared_with.contains_key("dave_near"));
+        assert!(contract.verify_integrity("bob_near".to_string()));
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        assert!(contract.shared_with_me("dave_near".to_string()).is_empty());
+    }
+
+    #[test]
+    fn expired_shares_are_not_listed() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.generate_new_password("vpn".to_string(), "team@email.com".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], Some(100));
+        contract.share_with_many("vpn".to_string(), vec!["erin_near".to_string()], None);
+
+        context.block_timestamp = 100;
+        testing_env!(context);
+        assert_eq!(vec!["erin_near".to_string()], contract.list_shares("vpn".to_string()));
+    }
+
+    #[test]
+    fn rotate_and_revoke_shares_clears_access() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "team".to_string(), "old-ciphertext".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["erin_near".to_string(), "dave_near".to_string()], None);
+
+        let revoked = contract.rotate_and_revoke_shares("vpn".to_string(), "new-ciphertext".to_string());
+        assert_eq!(vec!["dave_near".to_string(), "erin_near".to_string()], revoked);
+        assert!(contract.list_shares("vpn".to_string()).is_empty());
+        assert_eq!("new-ciphertext", contract.get_password(&"bob_near".to_string(), &"vpn".to_string()));
+        assert_eq!(
+            r#"{"event":"shares_revoked","data":{"owner":"bob_near","resource":"vpn","grantees":["dave_near","erin_near"]}}"#,
+            shares_revoked_event("bob_near", "vpn", &revoked)
+        );
+    }
+
+    #[test]
+    fn owner_methods_never_return_key_material() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        let secret = "s3cr3t-Value!";
+        contract.set_password("email".to_string(), "bob".to_string(), secret.to_string());
+        contract.set_notes("email".to_string(), secret.to_string());
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context.clone());
+        contract.set_password("vpn".to_string(), "dave".to_string(), secret.to_string());
+
+        let outputs = as_owner(&mut contract, |c| {
+            c.set_strict_policy(true);
+            c.set_warn_weak_passwords(true);
+            c.set_entropy_mode(EntropyMode::DeterministicCounter);
+            c.set_max_credentials_per_account(10);
+            c.add_allowed_account("bob_near".to_string());
+            c.set_allowlist_enforced(false);
+            c.set_emergency_account(Some("erin_near".to_string()));
+            c.set_password_policy(PasswordPolicy::default());
+            c.set_site_rules("example.com".to_string(), SiteRules::default());
+            c.set_deletion_grace_period(0);
+            vec![
+                json!(c.is_strict_policy()),
+                json!(c.get_entropy_mode()),
+                json!(c.is_warn_weak_passwords()),
+                json!(c.get_max_credentials_per_account()),
+                json!(c.get_allowed_accounts()),
+                json!(c.is_allowlist_enforced()),
+                json!(c.get_emergency_account()),
+                json!(c.get_password_policy()),
+                json!(c.get_deletion_grace_period()),
+                json!(c.find_org_reuse()),
+                json!(c.verify_share_indexes()),
+                json!(c.compact()),
+                json!(c.erase_account("dave_near".to_string())),
+            ]
+        });
+        for output in outputs {
+            assert!(!output.to_string().contains(secret), "{} exposes key material", output);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The owner cannot be the emergency account")]
+    fn owner_cannot_be_emergency_account() {
+        owned_contract(|c| c.set_emergency_account(Some("alice_near".to_string())));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'alice_near' is not allowed to read 'email' of 'bob_near'")]
+    fn owner_cannot_read_user_keys() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        context.signer_account_id = "alice_near".to_string();
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context);
+        contract.get_password_opt("bob_near".to_string(), "email".to_string());
+    }
+
+    #[test]
+    fn auto_lock_requires_a_recent_unlock() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_auto_lock(1_000);
+        assert!(contract.is_locked("bob_near".to_string()));
+
+        contract.unlock();
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"vpn".to_string()));
+
+        context.block_timestamp = 1_000;
+        testing_env!(context.clone());
+        assert!(contract.is_locked("bob_near".to_string()));
+        contract.unlock();
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"vpn".to_string()));
+
+        contract.set_auto_lock(0);
+        assert!(!contract.is_locked("bob_near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' is locked, call unlock first")]
+    fn reads_fail_once_the_inactivity_window_passes() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_auto_lock(1_000);
+        contract.unlock();
+
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        contract.get_password(&"bob_near".to_string(), &"vpn".to_string());
+    }
+
+    #[test]
+    fn activity_keeps_the_account_unlocked() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_auto_lock(1_000);
+        contract.unlock();
+
+        context.block_timestamp = 900;
+        testing_env!(context.clone());
+        contract.set_notes("vpn".to_string(), "office".to_string());
+
+        context.block_timestamp = 1_800;
+        testing_env!(context.clone());
+        assert_eq!(Some("secret".to_string()), contract.access_password("bob_near".to_string(), "vpn".to_string()));
+
+        context.block_timestamp = 2_700;
+        testing_env!(context.clone());
+        assert!(!contract.is_locked("bob_near".to_string()));
+
+        context.block_timestamp = 2_800;
+        testing_env!(context);
+        assert!(contract.is_locked("bob_near".to_string()));
+    }
+
+    // A contract where bob_near stored a rotated key and then let its auto-lock run out
+    fn locked_contract() -> Keychain {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "first".to_string());
+        contract.set_password("vpn".to_string(), "bob".to_string(), "second".to_string());
+        contract.set_auto_lock(1_000);
+        contract.unlock();
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' is locked, call unlock first")]
+    fn locked_account_cannot_export_borsh() {
+        locked_contract().export_borsh("bob_near".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' is locked, call unlock first")]
+    fn locked_account_cannot_export_with_hash() {
+        locked_contract().export_with_hash("bob_near".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' is locked, call unlock first")]
+    fn locked_account_cannot_read_change_history() {
+        locked_contract().change_history("bob_near".to_string(), "vpn".to_string());
+    }
+
+    #[test]
+    fn reveal_token_works_once() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        let token = "first-token".to_string();
+        let other = "second-token".to_string();
+        contract.create_reveal_token("VPN".to_string(), sha256_hex(&token), 1_000);
+        contract.create_reveal_token("vpn".to_string(), sha256_hex(&other), 1_000);
+
+        context.signer_account_id = "support_near".to_string();
+        testing_env!(context);
+        assert_eq!(Some("secret".to_string()), contract.redeem_reveal_token("bob_near".to_string(), token.clone()));
+        assert_eq!(None, contract.redeem_reveal_token("bob_near".to_string(), token));
+        assert_eq!(None, contract.redeem_reveal_token("dave_near".to_string(), other.clone()));
+        assert_eq!(Some("secret".to_string()), contract.redeem_reveal_token("bob_near".to_string(), other));
+    }
+
+    #[test]
+    fn expired_reveal_token_reveals_nothing() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        let token = "token".to_string();
+        contract.create_reveal_token("vpn".to_string(), sha256_hex(&token), 1_000);
+
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        assert_eq!(None, contract.redeem_reveal_token("bob_near".to_string(), token));
+        assert!(contract.reveal_tokens.is_empty());
+    }
+
+    #[test]
+    fn reveal_token_hash_reveals_nothing() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        let token_hash = sha256_hex("token");
+        contract.create_reveal_token("vpn".to_string(), token_hash.clone(), 1_000);
+
+        // Anyone can read the stored hash, only the preimage redeems it
+        context.signer_account_id = "eve_near".to_string();
+        testing_env!(context);
+        assert_eq!(None, contract.redeem_reveal_token("bob_near".to_string(), token_hash));
+        assert_eq!(Some("secret".to_string()), contract.redeem_reveal_token("bob_near".to_string(), "token".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Token hash must be a lowercase hex-encoded sha256 digest")]
+    fn reveal_token_needs_a_hash() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        contract.create_reveal_token("vpn".to_string(), "token".to_string(), 1_000);
+    }
+
+    #[test]
+    fn emergency_account_can_break_glass() {
+        let mut contract = owned_contract(|c| c.set_emergency_account(Some("sos_near".to_string())));
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        contract.consent_to_emergency_access(Some("sos_near".to_string()));
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "sos_near".to_string();
+        context.block_timestamp = 42;
+        testing_env!(context);
+        assert_eq!("secret", contract.break_glass_read("bob_near".to_string(), "vpn".to_string()));
+        assert_eq!(
+            Some(&r#"{"event":"break_glass_access","data":{"caller":"sos_near","owner":"bob_near","resource":"vpn","timestamp":42}}"#.to_string()),
+            get_logs().last()
+        );
+        assert_eq!(Some("sos_near".to_string()), contract.get_emergency_account());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' has not consented to emergency access by 'sos_near'")]
+    fn owner_named_emergency_account_needs_consent() {
+        let mut contract = owned_contract(|c| c.set_emergency_account(Some("sos_near".to_string())));
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "sos_near".to_string();
+        testing_env!(context);
+        contract.break_glass_read("bob_near".to_string(), "vpn".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' has not consented to emergency access by 'alt_near'")]
+    fn consent_does_not_follow_a_new_emergency_account() {
+        let mut contract = owned_contract(|c| c.set_emergency_account(Some("sos_near".to_string())));
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        contract.consent_to_emergency_access(Some("sos_near".to_string()));
+        as_owner(&mut contract, |c| c.set_emergency_account(Some("alt_near".to_string())));
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "alt_near".to_string();
+        testing_env!(context);
+        contract.break_glass_read("bob_near".to_string(), "vpn".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'dave_near' is not the emergency account")]
+    fn other_accounts_cannot_break_glass() {
+        let mut contract = owned_contract(|c| c.set_emergency_account(Some("sos_near".to_string())));
+        contract.set_password("vpn".to_string(), "team".to_string(), "secret".to_string());
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.break_glass_read("bob_near".to_string(), "vpn".to_string());
+    }
+
+    #[test]
+    fn shared_with_me_lists_shares_from_all_owners() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "team".to_string(), "bob-vpn".to_string());
+        contract.share_with_many("vpn".to_string(), vec!["dave_near".to_string()], None);
+
+        context.signer_account_id = "erin_near".to_string();
+        testing_env!(context.clone());
+        contract.set_password("wiki".to_string(), "erin".to_string(), "erin-wiki".to_string());
+        contract.set_password("mail".to_string(), "erin".to_string(), "erin-mail".to_string());
+        contract.share_with_many("wiki".to_string(), vec!["dave_near".to_string()], None);
+        contract.share_with_many("mail".to_string(), vec!["dave_near".to_string()], Some(50));
+
+        assert_eq!(
+            vec![
+                ("bob_near".to_string(), "vpn".to_string()),
+                ("erin_near".to_string(), "mail".to_string()),
+                ("erin_near".to_string(), "wiki".to_string()),
+            ],
+            contract.shared_with_me("dave_near".to_string())
+        );
+
+        context.block_timestamp = 50;
+        testing_env!(context.clone());
+        assert_eq!(2, contract.shared_with_me("dave_near".to_string()).len());
+
+        contract.rotate_and_revoke_shares("wiki".to_string(), "erin-wiki-2".to_string());
+        assert_eq!(
+            vec![("bob_near".to_string(), "vpn".to_string())],
+            contract.shared_with_me("dave_near".to_string())
+        );
+        let indexed = contract.shared_with.get("dave_near").unwrap();
+        assert!(!indexed.contains(&("erin_near".to_string(), "wiki".to_string())));
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource 'vpn' of 'bob_near' is not shared with 'dave_near'")]
+    fn unshared_key_cannot_be_read() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.generate_new_password("vpn".to_string(), "team@email.com".to_string());
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.get_shared_password("bob_near".to_string(), "vpn".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "At most 32 grantees can be added at once")]
+    fn share_batch_is_bounded() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("vpn".to_string(), "team@email.com".to_string());
+        let grantees = (0..=MAX_SHARE_BATCH).map(|n| format!("user{}_near", n)).collect();
+        contract.share_with_many("vpn".to_string(), grantees, None);
+    }
+
+    // near-sdk 2.0 has no env::is_view to check up front, but every write needs the signer (or the
+    // predecessor for owner settings), which the runtime refuses to hand out in a view call
+    #[test]
+    #[should_panic(expected = "ProhibitedInView")]
+    fn writes_are_rejected_in_view_calls() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "ProhibitedInView")]
+    fn owner_settings_are_rejected_in_view_calls() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_strict_policy(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Random seed is unavailable in this context")]
+    fn generate_without_random_seed_fails_descriptively() {
+        let mut context = get_context(vec![], false);
+        context.random_seed = vec![];
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("email".to_string(), "bob@email.com".to_string());
+    }
+
+    #[test]
+    fn generate_with_min_classes_keeps_exact_length() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let minimums = ClassMinimums { lower: 2, upper: 2, digits: 2, special: 2 };
+        contract.generate_with_min_classes("bank".to_string(), "bob".to_string(), 8, minimums);
+        let password = contract.get_password(&"bob_near".to_string(), &"bank".to_string()).to_string();
+
+        assert_eq!(8, password.chars().count());
+        for class in &[LOWER_CASE_LETTERS, UPPER_CASE_LETTERS, NUMBERS, SPECIAL_CHARS] {
+            assert!(password.chars().filter(|c| class.contains(*c)).count() >= 2);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Minimum class counts add up to more than the password length")]
+    fn generate_with_min_classes_rejects_overfull_minimums() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let minimums = ClassMinimums { lower: 2, upper: 2, digits: 2, special: 1 };
+        contract.generate_with_min_classes("bank".to_string(), "bob".to_string(), 6, minimums);
+    }
+
+    #[test]
+    fn mobile_friendly_generation_uses_mobile_symbols_only() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        let allowed = [LOWER_CASE_LETTERS, UPPER_CASE_LETTERS, NUMBERS, MOBILE_SPECIAL_CHARS].concat();
+        for seed in 0..10u8 {
+            context.random_seed = vec![seed + 1; 32];
+            testing_env!(context.clone());
+            let options = GenerationOptions {
+                length: 32,
+                minimums: ClassMinimums { lower: 0, upper: 0, digits: 0, special: 8 },
+                mobile_friendly: true,
+                exclude: String::new(),
+                special_chars: None,
+            };
+            contract.generate_with_options("phone".to_string(), "bob".to_string(), options);
+            let password = contract.get_password(&"bob_near".to_string(), &"phone".to_string());
+            assert_eq!(32, password.len());
+            assert!(password.chars().all(|c| allowed.contains(c)), "{} isn't mobile friendly", password);
+        }
+    }
+
+    #[test]
+    fn excluded_characters_never_appear() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for seed in 0..10u8 {
+            context.random_seed = vec![seed + 1; 32];
+            testing_env!(context.clone());
+            let options = GenerationOptions {
+                length: 32,
+                minimums: ClassMinimums { lower: 0, upper: 0, digits: 0, special: 8 },
+                mobile_friendly: false,
+                exclude: "!@#$".to_string(),
+                special_chars: None,
+            };
+            contract.generate_with_options("legacy".to_string(), "bob".to_string(), options);
+            let password = contract.get_password(&"bob_near".to_string(), &"legacy".to_string());
+            assert_eq!(32, password.len());
+            assert!(!password.chars().any(|c| "!@#$".contains(c)), "{} has excluded characters", password);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Excluding '0123456789' leaves nothing to meet a class minimum")]
+    fn excluding_a_required_class_fails() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let options = GenerationOptions {
+            length: 8,
+            minimums: ClassMinimums { lower: 0, upper: 0, digits: 2, special: 0 },
+            mobile_friendly: false,
+            exclude: NUMBERS.to_string(),
+            special_chars: None,
+        };
+        contract.generate_with_options("legacy".to_string(), "bob".to_string(), options);
+    }
+
+    #[test]
+    #[should_panic(expected = "leaves no characters to generate from")]
+    fn excluding_every_character_fails() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let options = GenerationOptions {
+            length: 8,
+            minimums: ClassMinimums::default(),
+            mobile_friendly: false,
+            exclude: [LOWER_CASE_LETTERS, UPPER_CASE_LETTERS, NUMBERS, SPECIAL_CHARS].concat(),
+            special_chars: None,
+        };
+        contract.generate_with_options("legacy".to_string(), "bob".to_string(), options);
+    }
+
+    #[test]
+    fn custom_special_chars_are_used() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let options = GenerationOptions {
+            length: 16,
+            minimums: ClassMinimums { lower: 0, upper: 0, digits: 0, special: 16 },
+            mobile_friendly: false,
+            exclude: String::new(),
+            special_chars: Some("_-".to_string()),
+        };
+        contract.generate_with_options("legacy".to_string(), "bob".to_string(), options);
+        let password = contract.get_password(&"bob_near".to_string(), &"legacy".to_string());
+        assert!(password.chars().all(|c| c == '_' || c == '-'), "{} has other specials", password);
+    }
+
+    #[test]
+    #[should_panic(expected = "Custom special characters must not contain whitespace")]
+    fn custom_special_chars_reject_whitespace() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let options = GenerationOptions {
+            length: 16,
+            minimums: ClassMinimums::default(),
+            mobile_friendly: false,
+            exclude: String::new(),
+            special_chars: Some("! ?".to_string()),
+        };
+        contract.generate_with_options("legacy".to_string(), "bob".to_string(), options);
+    }
+
+    #[test]
+    #[should_panic(expected = "Generated password contains whitespace")]
+    fn exact_length_guard_catches_whitespace() {
+        assert_exact_length("ab cd", 5);
+    }
+
+    #[test]
+    fn generate_ephemeral_does_not_touch_state() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let contract = Keychain::default();
+        let state = contract.try_to_vec().unwrap();
+
+        let password = contract.generate_ephemeral(20, false, "client-entropy-1".to_string());
+        assert_eq!(20, password.len());
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(24, contract.generate_ephemeral(24, true, "client-entropy-1".to_string()).len());
+        assert_eq!(state, contract.try_to_vec().unwrap());
+    }
+
+    #[test]
+    fn generate_ephemeral_depends_on_caller_entropy() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let contract = Keychain::default();
+        assert_ne!(
+            contract.generate_ephemeral(20, true, "client-entropy-1".to_string()),
+            contract.generate_ephemeral(20, true, "client-entropy-2".to_string())
+        );
+    }
+
+    #[test]
+    fn generate_ephemeral_does_not_preview_the_counter() {
+        let mut contract = owned_contract(|c| c.set_entropy_mode(EntropyMode::DeterministicCounter));
+        let ephemeral = contract.generate_ephemeral(12, true, "client-entropy-1".to_string());
+        contract.generate_new_password("email".to_string(), "bob".to_string());
+        assert_ne!(ephemeral, contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Entropy must be at least 16 bytes")]
+    fn generate_ephemeral_needs_caller_entropy() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        Keychain::default().generate_ephemeral(20, true, "short".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Password length must be between 4 and 128")]
+    fn generate_ephemeral_checks_length() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        Keychain::default().generate_ephemeral(2, true, "client-entropy-1".to_string());
+    }
+
+    #[test]
+    fn regenerate_same_params_reuses_recorded_options() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let options = GenerationOptions {
+            length: 20,
+            minimums: ClassMinimums { lower: 0, upper: 0, digits: 4, special: 0 },
+            mobile_friendly: false,
+            exclude: "0O".to_string(),
+            special_chars: None,
+        };
+        contract.generate_with_options("vpn".to_string(), "bob".to_string(), options);
+        let params = contract.get_generation_params("bob_near".to_string(), "vpn".to_string()).unwrap();
+        assert_eq!(
+            r#"{"length":20,"minimums":{"lower":0,"upper":0,"digits":4,"special":0},"mobile_friendly":false,"exclude":"0O","special_chars":null}"#,
+            params
+        );
+
+        let first = contract.get_password(&"bob_near".to_string(), &"vpn".to_string()).to_string();
+        contract.regenerate_same_params("vpn".to_string());
+        let second = contract.get_password(&"bob_near".to_string(), &"vpn".to_string()).to_string();
+        assert_ne!(first, second);
+        assert_eq!(20, second.len());
+        assert!(second.chars().filter(|c| c.is_ascii_digit()).count() >= 4);
+        assert!(!second.contains('0') && !second.contains('O'));
+        assert_eq!(Some(params), contract.get_generation_params("bob_near".to_string(), "vpn".to_string()));
+
+        // A value set by hand was not generated with those options
+        contract.set_password("vpn".to_string(), "bob".to_string(), "typed-by-hand".to_string());
+        assert_eq!(None, contract.get_generation_params("bob_near".to_string(), "vpn".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource 'vpn' has no recorded generation parameters")]
+    fn regenerate_same_params_needs_recorded_options() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "typed-by-hand".to_string());
+        contract.regenerate_same_params("vpn".to_string());
+    }
+
+    #[test]
+    fn regenerate_password_never_returns_a_previous_value() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("email".to_string(), "bob@email.com".to_string());
+        let mut seen = vec![contract.get_password(&"bob_near".to_string(), &"email".to_string()).to_string()];
+        for _ in 0..5 {
+            contract.regenerate_password("email".to_string());
+            let password = contract.get_password(&"bob_near".to_string(), &"email".to_string()).to_string();
+            assert_ne!(seen.last().unwrap(), &password);
+            assert!(!seen.contains(&password));
+            seen.push(password);
+        }
+        assert_eq!("bob@email.com", contract.key("bob_near", "email").unwrap().identifier);
+    }
+
+    #[test]
+    fn rotation_count_counts_changed_values() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "first".to_string());
+        assert_eq!(0, contract.get_rotation_count("bob_near".to_string(), "email".to_string()));
+
+        contract.set_password("email".to_string(), "bob".to_string(), "second".to_string());
+        contract.set_password("email".to_string(), "bob".to_string(), "second".to_string());
+        contract.regenerate_password("email".to_string());
+        assert_eq!(2, contract.get_rotation_count("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    fn generate_with_requirements_meets_every_requirement() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for seed in 0..20u8 {
+            context.random_seed = vec![seed + 1; 32];
+            testing_env!(context.clone());
+            contract.generate_with_requirements("bank".to_string(), "bob".to_string(), 40, true, 1, "a".to_string());
+            let password = contract.get_password(&"bob_near".to_string(), &"bank".to_string());
+            assert_eq!(40, password.len());
+            assert!(password.starts_with(|c: char| c.is_ascii_alphabetic()), "{}", password);
+            assert!(!has_consecutive_repeats(password), "{}", password);
+            assert!(!password.contains('a'), "{}", password);
+        }
+    }
+
+    #[test]
+    fn generate_batch_stores_distinct_passwords() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let resources = ["email", "bank", "vpn", "wiki", "forum"];
+        let entries = resources.iter().map(|resource| (resource.to_string(), "bob@email.com".to_string())).collect();
+        contract.generate_batch(entries, 14);
+
+        let mut passwords: Vec<String> = resources.iter()
+            .map(|resource| contract.get_password(&"bob_near".to_string(), &resource.to_string()).to_string())
+            .collect();
+        assert!(passwords.iter().all(|password| password.len() == 14));
+        passwords.sort();
+        passwords.dedup();
+        assert_eq!(resources.len(), passwords.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource 'Email' is listed more than once")]
+    fn generate_batch_rejects_repeated_resources() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let entries = vec![("email".to_string(), "bob".to_string()), ("Email".to_string(), "bob".to_string())];
+        contract.generate_batch(entries, 14);
+    }
+
+    #[test]
+    fn regenerate_min_entropy_meets_target() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "1234".to_string());
+        for min_bits in [20, 60, 128, 256] {
+            contract.regenerate_min_entropy("email".to_string(), min_bits);
+            let password = contract.get_password(&"bob_near".to_string(), &"email".to_string());
+            assert!(estimate_entropy_bits(password) >= min_bits as f64, "{} bits for {}", estimate_entropy_bits(password), min_bits);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "1000 bits of entropy would take more than the maximum of 128 characters")]
+    fn regenerate_min_entropy_rejects_unreachable_targets() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "1234".to_string());
+        contract.regenerate_min_entropy("email".to_string(), 1000);
+    }
+
+    #[test]
+    fn rotate_due_only_rotates_expired_keys() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("token".to_string(), "ci".to_string(), "token-value".to_string());
+        contract.set_password("email".to_string(), "bob".to_string(), "email-value".to_string());
+        contract.set_password("bank".to_string(), "bob".to_string(), "bank-value".to_string());
+        contract.set_rotation_interval("token".to_string(), Some(100));
+        contract.set_rotation_interval("email".to_string(), Some(500));
+
+        context.block_timestamp = 1_200;
+        testing_env!(context.clone());
+        assert_eq!(vec!["token".to_string()], contract.rotate_due());
+        assert_ne!("token-value", contract.get_password(&"bob_near".to_string(), &"token".to_string()));
+        assert_eq!("email-value", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert_eq!("bank-value", contract.get_password(&"bob_near".to_string(), &"bank".to_string()));
+        assert_eq!(Some(100), contract.get_rotation_interval("bob_near".to_string(), "token".to_string()));
+
+        // The rotation counts as an update, so the key isn't due again right away
+        assert!(contract.rotate_due().is_empty());
+    }
+
+    #[test]
+    fn generate_without_repeats_never_repeats() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let minimums = ClassMinimums { lower: 1, upper: 1, digits: 1, special: 1 };
+        contract.generate_without_repeats("bank".to_string(), "bob".to_string(), 64, minimums);
+        let password = contract.get_password(&"bob_near".to_string(), &"bank".to_string());
+        assert_eq!(64, password.len());
+        assert!(!has_consecutive_repeats(password));
+    }
+
+    #[test]
+    fn generate_without_repeats_falls_back_to_repair() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        // Without any attempts the repair path always runs; 64 digits are all but certain to repeat
+        let password = generate_password_without_repeats(&mut seeded_rng(), 64, &[(NUMBERS, 64)], 0);
+        assert_eq!(64, password.len());
+        assert!(password.chars().all(|c| NUMBERS.contains(c)));
+        assert!(!has_consecutive_repeats(&password));
+    }
+
+    #[test]
+    fn validate_generation_params_returns_distinct_pool() {
+        assert_eq!(vec!['a', 'b', '1'], validate_generation_params(4, &[("ab", 1), ("b1", 2)]));
+        assert_eq!(10, validate_generation_params(MAX_PASSWORD_LENGTH, &classes_from_flags(CHARSET_DIGITS)).len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Password length must be between 4 and 128")]
+    fn validate_generation_params_rejects_short_length() {
+        validate_generation_params(3, &[(NUMBERS, 0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Password length must be between 4 and 128")]
+    fn validate_generation_params_rejects_long_length() {
+        validate_generation_params(129, &[(NUMBERS, 0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one character set must be selected")]
+    fn validate_generation_params_rejects_empty_charset() {
+        validate_generation_params(8, &classes_from_flags(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Minimum class counts add up to more than the password length")]
+    fn validate_generation_params_rejects_overfull_minimums() {
+        validate_generation_params(8, &[(LOWER_CASE_LETTERS, 5), (NUMBERS, 4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Generated password has 3 characters instead of the requested 4")]
+    fn exact_length_guard_catches_short_passwords() {
+        assert_exact_length("abc", 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Password must not be the same as the account id")]
+    fn strict_policy_rejects_account_id_password() {
+        let mut contract = owned_contract(|c| c.set_strict_policy(true));
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "BOB_NEAR".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Password must not be the same as the identifier")]
+    fn strict_policy_rejects_identifier_password() {
+        let mut contract = owned_contract(|c| c.set_strict_policy(true));
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "Bob@Email.com".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Password must not be the same as the resource name")]
+    fn strict_policy_rejects_resource_password() {
+        let mut contract = owned_contract(|c| c.set_strict_policy(true));
+        contract.set_password("GitHub".to_string(), "bob@email.com".to_string(), "github".to_string());
+    }
+
+    #[test]
+    fn trivial_passwords_are_only_flagged_by_default() {
+        let mut contract = owned_contract(|c| c.set_strict_policy(true));
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "x7#Kp9!qLm2$".to_string());
+        assert_eq!("x7#Kp9!qLm2$", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "email".to_string());
+        assert_eq!("email", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        assert_eq!(
+            Some(&r#"{"event":"trivial_password","data":{"account_id":"bob_near","resource":"email"}}"#.to_string()),
+            get_logs().last()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn strict_policy_is_owner_only() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_strict_policy(true);
+    }
+
+    #[test]
+    fn strength_guard_allows_stronger_and_forced_replacements() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_strength_guard(true);
+        contract.set_password("email".to_string(), "bob".to_string(), "x7#Kp9!q".to_string());
+        contract.set_password("email".to_string(), "bob".to_string(), "x7#Kp9!qLm2$Vw4z".to_string());
+        contract.set_password_with_force("email".to_string(), "bob".to_string(), "4821".to_string(), true);
+        assert_eq!("4821", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+
+        contract.set_strength_guard(false);
+        contract.set_password("email".to_string(), "bob".to_string(), "ab".to_string());
+        assert!(!contract.is_strength_guarded("bob_near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "New password is weaker than the current one, pass force to replace it anyway")]
+    fn strength_guard_rejects_weaker_replacement() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_strength_guard(true);
+        contract.set_password("email".to_string(), "bob".to_string(), "x7#Kp9!qLm2$Vw4zR8&n".to_string());
+        contract.set_password("email".to_string(), "bob".to_string(), "4821".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "New password is weaker than the current one, pass force to replace it anyway")]
+    fn strength_guard_covers_imported_values() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_strength_guard(true);
+        contract.set_password("bank".to_string(), "bob".to_string(), "x7#Kp9!qLm2$Vw4zR8&n".to_string());
+        contract.import_borsh(crafted_backup(|entry| entry.enc_password = "4821".to_string()));
+    }
+
+    #[test]
+    fn strength_guard_never_blocks_rotation() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_strength_guard(true);
+        let ciphertext = "x7#Kp9!qLm2$Vw4zR8&nT5^bQ1@eY6*uI3(oP0)aS".repeat(3);
+        contract.set_password("email".to_string(), "bob".to_string(), ciphertext.clone());
+        contract.set_rotation_interval("email".to_string(), Some(100));
+
+        context.block_timestamp = 100;
+        testing_env!(context);
+        assert_eq!(vec!["email".to_string()], contract.rotate_due());
+        assert_ne!(ciphertext, contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+        contract.regenerate_password("email".to_string());
+    }
+
+    #[test]
+    fn weak_password_warning_only_fires_for_weak_values_when_enabled() {
+        let weak_stored = r#"{"event":"weak_password_stored","data":{"account_id":"bob_near","resource":"email"}}"#;
+        let mut contract = owned_contract(|_| {});
+        contract.set_password("email".to_string(), "bob".to_string(), "qwerty".to_string());
+        assert!(!get_logs().iter().any(|log| log == weak_stored));
+
+        as_owner(&mut contract, |c| c.set_warn_weak_passwords(true));
+        contract.set_password("email".to_string(), "bob".to_string(), "x7#Kp9!qLm2$".to_string());
+        assert!(!get_logs().iter().any(|log| log == weak_stored));
+        contract.set_password("email".to_string(), "bob".to_string(), "qwerty".to_string());
+        assert_eq!(Some(&weak_stored.to_string()), get_logs().last());
+
+        // The weak value is still stored
+        assert_eq!("qwerty", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+    }
+
+    #[test]
+    fn generate_bounded_fills_the_site_maximum_and_meets_the_policy() {
+        let policy = PasswordPolicy {
+            min_length: 12,
+            require_lower: true,
+            require_upper: true,
+            require_digit: true,
+            require_special: true,
+        };
+        let mut contract = owned_contract(|c| c.set_password_policy(policy.clone()));
+        contract.generate_bounded("bank".to_string(), "bob".to_string(), 8, 16);
+        let password = contract.get_password(&"bob_near".to_string(), &"bank".to_string());
+        assert_eq!(16, password.chars().count());
+        assert!(policy.violations(password).is_empty());
+
+        contract.generate_bounded("forum".to_string(), "bob".to_string(), 12, 1000);
+        assert_eq!(MAX_PASSWORD_LENGTH as usize, contract.get_password(&"bob_near".to_string(), &"forum".to_string()).len());
+    }
+
+    #[test]
+    #[should_panic(expected = "The password policy needs at least 20 characters, more than the site's maximum of 16")]
+    fn generate_bounded_rejects_a_maximum_too_short_for_the_policy() {
+        let mut contract = owned_contract(|c| c.set_password_policy(PasswordPolicy { min_length: 20, ..PasswordPolicy::default() }));
+        contract.generate_bounded("bank".to_string(), "bob".to_string(), 8, 16);
+    }
+
+    #[test]
+    fn allowlisted_account_can_write() {
+        let mut contract = owned_contract(|c| {
+            c.add_allowed_account("bob_near".to_string());
+            c.set_allowlist_enforced(true);
+        });
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_tags("email".to_string(), vec!["work".to_string()]);
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' is not allowed to store keys")]
+    fn non_allowlisted_account_is_rejected() {
+        let mut contract = owned_contract(|c| {
+            c.add_allowed_account("dave_near".to_string());
+            c.set_allowlist_enforced(true);
+        });
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' is not allowed to store keys")]
+    fn non_allowlisted_account_cannot_merge() {
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "old_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "old@email.com".to_string(), "theirs".to_string());
+        contract.approve_merge("bob_near".to_string());
+        as_owner(&mut contract, |c| {
+            c.add_allowed_account("old_near".to_string());
+            c.set_allowlist_enforced(true);
+        });
+
+        context.signer_account_id = "bob_near".to_string();
+        testing_env!(context);
+        contract.merge_from("old_near".to_string(), "take_theirs".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account 'bob_near' is not allowed to store keys")]
+    fn non_allowlisted_account_cannot_replace_identifier_domains() {
+        let mut contract = owned_contract(|_| {});
+        contract.set_password("email".to_string(), "bob@old.com".to_string(), "secret".to_string());
+        as_owner(&mut contract, |c| {
+            c.add_allowed_account("dave_near".to_string());
+            c.set_allowlist_enforced(true);
+        });
+        contract.replace_identifier_domain("old.com".to_string(), "new.com".to_string());
+    }
+
+    #[test]
+    fn allowlist_is_ignored_until_enforced() {
+        let mut contract = owned_contract(|c| {
+            c.add_allowed_account("dave_near".to_string());
+            c.add_allowed_account("dave_near".to_string());
+        });
+        assert_eq!(vec!["dave_near".to_string()], contract.get_allowed_accounts());
+
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"email".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "An account can store at most 3 credentials")]
+    fn credential_cap_rejects_extra_writes() {
+        let mut contract = owned_contract(|c| c.set_max_credentials_per_account(3));
+        for resource in &["email", "bank", "forum"] {
+            contract.set_password(resource.to_string(), "bob".to_string(), "secret".to_string());
+        }
+        assert_eq!(3, contract.get_max_credentials_per_account());
+        // Updating an existing credential doesn't count against the cap
+        contract.set_password("email".to_string(), "bob".to_string(), "rotated".to_string());
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "An account can store at most 2 credentials")]
+    fn credential_cap_covers_merged_keys_across_vaults() {
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "old_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "old".to_string(), "secret".to_string());
+        contract.approve_merge("bob_near".to_string());
+        context.signer_account_id = "bob_near".to_string();
+        testing_env!(context);
+        contract.set_password_in_vault("work".to_string(), "vpn".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_password_in_vault("work".to_string(), "wiki".to_string(), "bob".to_string(), "secret".to_string());
+        as_owner(&mut contract, |c| c.set_max_credentials_per_account(2));
+        contract.merge_from("old_near".to_string(), "take_theirs".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "An account can store at most 1 credentials")]
+    fn credential_cap_covers_restored_keys() {
+        let mut contract = owned_contract(|c| c.set_deletion_grace_period(100));
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.delete_password("email".to_string());
+        contract.set_password("bank".to_string(), "bob".to_string(), "secret".to_string());
+        as_owner(&mut contract, |c| c.set_max_credentials_per_account(1));
+        contract.restore_password("email".to_string());
+    }
+
+    #[test]
+    fn raising_credential_cap_allows_more_writes() {
+        let mut contract = owned_contract(|c| c.set_max_credentials_per_account(1));
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        as_owner(&mut contract, |c| c.set_max_credentials_per_account(2));
+        contract.set_password("bank".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!(2, contract.keys["bob_near"].len());
+    }
+
+    #[test]
+    fn check_compliance_reports_reasons() {
+        let contract = owned_contract(|c| c.set_password_policy(PasswordPolicy {
+            min_length: 10,
+            require_lower: true,
+            require_upper: true,
+            require_digit: true,
+            require_special: true,
+        }));
+        assert_eq!((true, vec![]), contract.check_compliance("Tr0ub4dor&3x".to_string()));
+        assert_eq!(
+            (false, vec![
+                "too short, at least 10 characters required".to_string(),
+                "missing digit".to_string(),
+                "missing special character".to_string(),
+            ]),
+            contract.check_compliance("Password".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn password_policy_is_owner_only() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password_policy(PasswordPolicy::default());
+    }
+
+    #[test]
+    fn resource_theme_seed_is_stable() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let seed = Keychain::resource_theme_seed("GitHub".to_string());
+        assert_eq!(seed, Keychain::resource_theme_seed("github".to_string()));
+        assert_ne!(seed, Keychain::resource_theme_seed("gitlab".to_string()));
+        assert_ne!(seed, Keychain::resource_theme_seed("email".to_string()));
+    }
+
+    #[test]
+    fn derive_password_is_deterministic() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        let derive = |resource: &str, flags| {
+            Keychain::derive_password("correct horse".to_string(), resource.to_string(), 20, flags)
+        };
+        let all = CHARSET_LOWER | CHARSET_UPPER | CHARSET_DIGITS | CHARSET_SPECIAL;
+        assert_eq!(derive("github", all), derive("github", all));
+        assert_ne!(derive("github", all), derive("gitlab", all));
+        assert_eq!(20, derive("github", all).len());
+        assert!(derive("github", CHARSET_DIGITS).chars().all(|c| NUMBERS.contains(c)));
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one character set must be selected")]
+    fn derive_password_requires_a_charset() {
+        let context = get_context(vec![], true);
+        testing_env!(context);
+        Keychain::derive_password("correct horse".to_string(), "github".to_string(), 20, 0);
+    }
+
+    #[test]
+    fn generate_pin_without_leading_zero() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for seed in 0..20u8 {
+            context.random_seed = vec![seed + 1; 32];
+            testing_env!(context.clone());
+            contract.generate_pin("phone".to_string(), "bob".to_string(), 6, false);
+            let pin = contract.get_password(&"bob_near".to_string(), &"phone".to_string());
+            assert_eq!(6, pin.len());
+            assert!(pin.chars().all(|c| c.is_ascii_digit()));
+            assert!(!pin.starts_with('0'));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "A PIN must have between 4 and 12 digits")]
+    fn generate_pin_validates_digits() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_pin("phone".to_string(), "bob".to_string(), 3, true);
+    }
+
+    #[test]
+    fn generate_from_alphabet_uses_only_alphabet() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_from_alphabet("dna".to_string(), "bob".to_string(), 24, "ACGTAC".to_string());
+        let password = contract.get_password(&"bob_near".to_string(), &"dna".to_string());
+        assert_eq!(24, password.len());
+        assert!(password.chars().all(|c| "ACGT".contains(c)));
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one character set must be selected")]
+    fn generate_from_alphabet_rejects_empty_alphabet() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_from_alphabet("dna".to_string(), "bob".to_string(), 24, "".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Alphabet may only contain printable ASCII characters other than space, found ' '")]
+    fn generate_from_alphabet_rejects_whitespace() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_from_alphabet("dna".to_string(), "bob".to_string(), 24, "AC GT".to_string());
+    }
+
+    // Generates two passwords on a fresh contract in the given mode, each in a block with its own seed
+    fn generate_two(mode: EntropyMode, first_seed: u8) -> (String, String) {
+        let mut context = get_context(vec![], false);
+        context.random_seed = vec![first_seed; 32];
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        as_owner(&mut contract, |c| c.set_entropy_mode(mode));
+        contract.generate_with_min_classes("first".to_string(), "bob".to_string(), 16, ClassMinimums::default());
+        context.random_seed = vec![first_seed + 1; 32];
+        testing_env!(context);
+        contract.generate_with_min_classes("second".to_string(), "bob".to_string(), 16, ClassMinimums::default());
+        (
+            contract.get_password(&"bob_near".to_string(), &"first".to_string()).to_string(),
+            contract.get_password(&"bob_near".to_string(), &"second".to_string()).to_string(),
+        )
+    }
+
+    #[test]
+    fn deterministic_entropy_is_reproducible() {
+        let sequence = generate_two(EntropyMode::DeterministicCounter, 1);
+        assert_eq!(sequence, generate_two(EntropyMode::DeterministicCounter, 7));
+        assert_ne!(sequence.0, sequence.1);
+    }
+
+    #[test]
+    fn blockchain_entropy_follows_random_seed() {
+        assert_ne!(generate_two(EntropyMode::BlockchainRandom, 1), generate_two(EntropyMode::BlockchainRandom, 7));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn entropy_mode_is_owner_only() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_entropy_mode(EntropyMode::DeterministicCounter);
+    }
+
+    #[test]
+    fn get_historical_password_after_rotations() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        for password in &["first", "second", "third", "fourth"] {
+            contract.set_password("email".to_string(), "bob@email.com".to_string(), password.to_string());
+        }
+        let version = |versions_back| {
+            contract.get_historical_password("bob_near".to_string(), "email".to_string(), versions_back)
+        };
+        assert_eq!(Some("fourth".to_string()), version(0));
+        assert_eq!(Some("third".to_string()), version(1));
+        assert_eq!(Some("second".to_string()), version(2));
+        assert_eq!(Some("first".to_string()), version(3));
+        assert_eq!(None, version(4));
+    }
+
+    #[test]
+    fn change_history_records_rotation_times() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        for (n, timestamp) in [100u64, 200, 300, 400].iter().enumerate() {
+            context.block_timestamp = *timestamp;
+            testing_env!(context.clone());
+            contract.set_password("email".to_string(), "bob".to_string(), format!("password{}", n));
+        }
+        assert_eq!(
+            vec![(200, "password0".to_string()), (300, "password1".to_string()), (400, "password2".to_string())],
+            contract.change_history("bob_near".to_string(), "email".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only 'bob_near' can read its change history")]
+    fn change_history_is_owner_only() {
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        Keychain::default().change_history("bob_near".to_string(), "email".to_string());
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        for n in 0..=MAX_HISTORY + 1 {
+            contract.set_password("email".to_string(), "bob@email.com".to_string(), format!("password{}", n));
+        }
+        let key = contract.key("bob_near", "email").unwrap();
+        assert_eq!(MAX_HISTORY, key.history.len());
+        assert_eq!("password1", key.history[0].1);
+    }
+
+    #[test]
+    fn names_at_length_limits_are_accepted() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let resource = "r".repeat(MAX_RESOURCE_BYTES);
+        contract.set_password(resource.clone(), "i".repeat(MAX_IDENTIFIER_BYTES), "secret".to_string());
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &resource));
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource name is 129 bytes long, the limit is 128")]
+    fn too_long_resource_is_rejected() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("r".repeat(MAX_RESOURCE_BYTES + 1), "bob".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Identifier is 257 bytes long, the limit is 256")]
+    fn too_long_identifier_is_rejected() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "i".repeat(MAX_IDENTIFIER_BYTES + 1), "secret".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Identifier is 258 bytes long, the limit is 256")]
+    fn identifier_limit_counts_bytes_not_characters() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "é".repeat(MAX_IDENTIFIER_BYTES / 2 + 1), "secret".to_string());
+    }
+
+    #[test]
+    fn notes_limit_counts_bytes_not_characters() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_notes("email".to_string(), "📝".repeat(MAX_NOTES_BYTES / 4));
+        assert_eq!(MAX_NOTES_BYTES, contract.keys["bob_near"]["email"].notes.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Notes are 4100 bytes long, the limit is 4096")]
+    fn multibyte_notes_over_limit_are_rejected() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_notes("email".to_string(), "📝".repeat(MAX_NOTES_BYTES / 4 + 1));
+    }
+
+    #[test]
+    fn reminder_shows_in_metadata_but_not_in_password() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!(None, contract.get_reminder("bob_near".to_string(), "vpn".to_string()));
+
+        contract.set_reminder("vpn".to_string(), Some("use VPN first".to_string()));
+        assert_eq!(Some("use VPN first".to_string()), contract.get_reminder("bob_near".to_string(), "vpn".to_string()));
+        let metadata = contract.get_key_metadata("bob_near".to_string(), "vpn".to_string()).unwrap();
+        assert_eq!(Some("use VPN first".to_string()), metadata.reminder);
+        assert_eq!("secret", contract.get_password(&"bob_near".to_string(), &"vpn".to_string()));
+
+        contract.set_reminder("vpn".to_string(), None);
+        assert_eq!(None, contract.get_reminder("bob_near".to_string(), "vpn".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Reminder is 257 bytes long, the limit is 256")]
+    fn overlong_reminder_is_rejected() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("vpn".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_reminder("vpn".to_string(), Some("a".repeat(MAX_REMINDER_BYTES + 1)));
+    }
+
+    #[test]
+    fn two_factor_method_round_trips() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        assert_eq!(None, contract.get_two_factor("bob_near".to_string(), "email".to_string()));
+
+        contract.set_two_factor("email".to_string(), Some("hardware_key".to_string()));
+        assert_eq!(Some("hardware_key".to_string()), contract.get_two_factor("bob_near".to_string(), "email".to_string()));
+        contract.set_two_factor("email".to_string(), None);
+        assert_eq!(None, contract.get_two_factor("bob_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown two-factor method 'carrier_pigeon', expected one of sms, email, app, hardware_key, push")]
+    fn unknown_two_factor_method_is_rejected() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob".to_string(), "secret".to_string());
+        contract.set_two_factor("email".to_string(), Some("carrier_pigeon".to_string()));
+    }
+
+    #[test]
+    fn unicode_identifier_and_notes_round_trip() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        let identifier = "ユーザー.名前@例え.jp";
+        let notes = "Ключ 🔑 — naïve café, 备注 👩‍💻";
+        contract.set_password("email".to_string(), identifier.to_string(), "秘密🔑".to_string());
+        contract.set_notes("email".to_string(), notes.to_string());
+
+        let metadata = contract.get_key_metadata("bob_near".to_string(), "email".to_string()).unwrap();
+        assert_eq!(identifier, metadata.identifier);
+        let json = serde_json::to_string(&metadata).unwrap();
+        assert_eq!(metadata, serde_json::from_str::<KeyMetadata>(&json).unwrap());
+
+        let state = Keychain::try_from_slice(&contract.try_to_vec().unwrap()).unwrap();
+        assert_eq!(notes, state.get_notes("bob_near".to_string(), "email".to_string()));
+        assert_eq!("秘密🔑", state.get_password(&"bob_near".to_string(), &"email".to_string()));
+
+        let data = contract.export_borsh("bob_near".to_string());
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.import_borsh(data);
+        assert_eq!(identifier, contract.keys["dave_near"]["email"].identifier);
+        assert_eq!(notes, contract.get_notes("dave_near".to_string(), "email".to_string()));
+    }
+
+    #[test]
+    fn detect_import_conflicts_reports_repeats_and_existing_keys() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("Email".to_string(), "bob".to_string(), "secret".to_string());
+        let entry = |resource: &str| (resource.to_string(), "bob".to_string(), "value".to_string());
+        let entries = vec![entry("vpn"), entry("email"), entry("wiki"), entry("VPN"), entry("vpn")];
+        assert_eq!(vec!["VPN".to_string(), "email".to_string()], contract.detect_import_conflicts(entries));
+        assert!(contract.detect_import_conflicts(vec![entry("bank")]).is_empty());
+    }
+
+    #[test]
+    fn export_borsh_round_trips_into_another_account() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Keychain::default();
+        contract.set_password_with_algorithm("email".to_string(), "bob@email.com".to_string(), "c2VjcmV0".to_string(), "aes-256-gcm".to_string());
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+        contract.set_tags("bank".to_string(), vec!["finance".to_string()]);
+        contract.set_custom_field("bank".to_string(), "pin".to_string(), "1234".to_string());
+        let data = contract.export_borsh("bob_near".to_string());
+
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.import_borsh(data.clone());
+        assert_eq!(data, contract.export_borsh("dave_near".to_string()));
+        assert_eq!("hunter2", contract.get_password(&"dave_near".to_string(), &"bank".to_string()));
+        assert_eq!(vec!["finance".to_string()], contract.get_tags("dave_near".to_string(), "bank".to_string()));
+        assert_eq!("aes-256-gcm", contract.get_key_metadata("dave_near".to_string(), "email".to_string()).unwrap().algorithm);
+        assert!(contract.verify_integrity("dave_near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Malformed backup data")]
+    fn import_borsh_rejects_malformed_data() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_borsh(vec![1, 0, 0, 0, 42]);
+    }
+
+    // A one-key backup as import_borsh reads it, changed by `edit` the way a crafted blob could be
+    fn crafted_backup(edit: impl FnOnce(&mut KeyBackup)) -> Vec<u8> {
+        let mut entry = KeyBackup {
+            resource: "bank".to_string(),
+            identifier: "bob".to_string(),
+            enc_password: "c2VjcmV0".to_string(),
+            algorithm: DEFAULT_ALGORITHM.to_string(),
+            custom_fields: vec![],
+            attachments: vec![],
+            tags: vec![],
+            url: String::new(),
+            security_qa: vec![],
+            backup_codes: vec![],
+            notes: String::new(),
+            archived: false,
+            clipboard_clear_secs: None,
+            reminder: None,
+            two_factor: None,
+        };
+        edit(&mut entry);
+        vec![entry].try_to_vec().unwrap()
+    }
+
+    #[test]
+    fn import_borsh_normalizes_tags() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_borsh(crafted_backup(|entry| entry.tags = vec!["  Work ".to_string(), "work".to_string()]));
+        assert_eq!(vec!["work".to_string()], contract.get_tags("bob_near".to_string(), "bank".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Notes are 4097 bytes long, the limit is 4096")]
+    fn import_borsh_enforces_field_limits() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_borsh(crafted_backup(|entry| entry.notes = "n".repeat(MAX_NOTES_BYTES + 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Attachment must be a content identifier of at most 128 bytes")]
+    fn import_borsh_validates_attachments() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_borsh(crafted_backup(|entry| entry.attachments = vec!["bafy beef".to_string()]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Clipboard clear timeout must be between 5 and 600 seconds")]
+    fn import_borsh_validates_clipboard_clear() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_borsh(crafted_backup(|entry| entry.clipboard_clear_secs = Some(0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Reminder is 257 bytes long, the limit is 256")]
+    fn import_borsh_validates_reminders() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.import_borsh(crafted_backup(|entry| entry.reminder = Some("r".repeat(MAX_REMINDER_BYTES + 1))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Password must not be the same as the resource")]
+    fn import_borsh_follows_the_strict_policy() {
+        let mut contract = owned_contract(|c| c.set_strict_policy(true));
+        contract.import_borsh(crafted_backup(|entry| entry.enc_password = "bank".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only 'bob_near' can export its keys")]
+    fn export_borsh_is_restricted_to_the_account() {
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = "dave_near".to_string();
+        testing_env!(context);
+        let contract = Keychain::default();
+        contract.export_borsh("bob_near".to_string());
+    }
+
+    #[test]
+    fn export_with_hash_verifies() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password("email".to_string(), "bob@email.com".to_string(), "secret".to_string());
+        contract.set_password("bank".to_string(), "bob".to_string(), "hunter2".to_string());
+
+        let (mut entries, hash) = contract.export_with_hash("bob_near".to_string());
+        assert_eq!(
+            vec![
+                ("bank".to_string(), "bob".to_string(), "hunter2".to_string()),
+                ("email".to_string(), "bob@email.com".to_string(), "secret".to_string()),
+            ],
+            entries
+        );
+        assert!(Keychain::verify_snapshot(entries.clone(), hash.clone()));
+
+        entries[0].2 = "tampered".to_string();
+        assert!(!Keychain::verify_snapshot(entries, hash));
+    }
+
+    #[test]
+    fn algorithm_tags_are_stored_per_key() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password_with_algorithm("email".to_string(), "bob".to_string(), "c1".to_string(), "aes-256-gcm".to_string());
+        contract.set_password_with_algorithm("bank".to_string(), "bob".to_string(), "c2".to_string(), "xchacha20poly1305".to_string());
+        contract.generate_new_password("forum".to_string(), "bob".to_string());
+
+        let algorithm = |resource: &str| {
+            contract.get_key_metadata("bob_near".to_string(), resource.to_string()).unwrap().algorithm
+        };
+        assert_eq!("aes-256-gcm", algorithm("email"));
+        assert_eq!("xchacha20poly1305", algorithm("bank"));
+        assert_eq!("none", algorithm("forum"));
+        assert_eq!(None, contract.get_key_metadata("bob_near".to_string(), "vpn".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown algorithm 'rot13'")]
+    fn unknown_algorithm_is_rejected() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_password_with_algorithm("email".to_string(), "bob".to_string(), "c1".to_string(), "rot13".to_string());
+    }
+
+    #[test]
+    fn set_then_get_by_hash() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        let resource_hash = to_hex(&env::sha256(b"github"));
+        contract.set_by_hash(resource_hash.clone(), "bob@email.com".to_string(), "ciphertext".to_string());
+
+        assert_eq!("ciphertext", contract.get_by_hash("bob_near".to_string(), resource_hash));
+        let state = contract.try_to_vec().unwrap();
+        assert!(!state.windows(b"github".len()).any(|window| window == b"github"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource hash must be a lowercase hex-encoded sha256 digest")]
+    fn set_by_hash_rejects_plaintext_names() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.set_by_hash("github".to_string(), "bob@email.com".to_string(), "ciphertext".to_string());
+    }
+
+    #[test]
+    fn add_list_and_remove_attachments() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("bank".to_string(), "bob".to_string());
+        let backup_codes = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi".to_string();
+        let certificate = "ar://bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U".to_string();
+        contract.add_attachment("bank".to_string(), backup_codes.clone());
+        contract.add_attachment("bank".to_string(), certificate.clone());
+        assert_eq!(
+            vec![backup_codes.clone(), certificate.clone()],
+            contract.list_attachments("bob_near".to_string(), "bank".to_string())
+        );
+
+        contract.remove_attachment("bank".to_string(), backup_codes);
+        assert_eq!(vec![certificate], contract.list_attachments("bob_near".to_string(), "bank".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "A key can hold at most 8 attachments")]
+    fn attachments_are_capped() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("bank".to_string(), "bob".to_string());
+        for n in 0..=MAX_ATTACHMENTS {
+            contract.add_attachment("bank".to_string(), format!("cid{}", n));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "A key can hold at most 16 custom fields")]
+    fn custom_fields_are_capped() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Keychain::default();
+        contract.generate_new_password("server".to_string(), "root".to_string());
+        for n in 0..=MAX_CUSTOM_FIELDS {
+            contract.set_custom_field("server".to_string(), format!("field{}", n), "value".to_string());
+        }
     }
 }
@@ -18,9 +18,11 @@ export async function initContract() {
   // Initializing our contract APIs by contract name and configuration
   window.contract = await new Contract(window.walletConnection.account(), nearConfig.contractName, {
     // View methods are read only. They don't modify the state, but usually return some value.
-    viewMethods: ['get_password'],
+    viewMethods: [],
     // Change methods can modify the state. But you don't receive the returned value when called.
-    changeMethods: ['generate_new_password'],
+    // get_password checks who is reading, so it goes through a signed transaction; near-api-js still
+    // resolves with its return value.
+    changeMethods: ['generate_new_password', 'get_password'],
   })
 }
 